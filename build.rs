@@ -0,0 +1,21 @@
+use std::process::Command;
+
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=TARGET={}", std::env::var("TARGET").unwrap());
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Only a hint: the binary usually runs on another host than the one building it
+    if !std::path::Path::new("/dev/mcelog").exists() {
+        println!("cargo:warning=/dev/mcelog is not available on this host, MCE events will not be collected here; the exporter warns again at startup");
+    }
+}
@@ -0,0 +1,71 @@
+use chrono::Local;
+use std::io;
+use std::net::IpAddr;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+/// Lines waiting for the writer; requests never wait on the log, lines
+/// beyond this backlog are dropped
+const BACKLOG: usize = 1024;
+
+/// One served request
+pub struct Entry<'a> {
+    pub client: IpAddr,
+    pub method: &'a str,
+    pub target: &'a str,
+    pub version: &'a str,
+    pub status: u16,
+    pub size: Option<u64>,
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+/// Combined Log Format access log, written by a background task
+pub struct AccessLog {
+    lines: mpsc::Sender<String>,
+}
+
+impl AccessLog {
+    /// `stdout` or a file path, appended to
+    pub async fn open(target: &str) -> io::Result<Self> {
+        let writer: Box<dyn AsyncWrite + Send + Unpin> = if target == "stdout" {
+            Box::new(tokio::io::stdout())
+        } else {
+            Box::new(OpenOptions::new().create(true).append(true).open(target).await?)
+        };
+        let (lines, pending) = mpsc::channel(BACKLOG);
+        tokio::spawn(write_lines(writer, pending));
+        Ok(Self { lines })
+    }
+
+    pub fn record(&self, entry: &Entry) {
+        let line = format!(
+            "{} - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\"\n",
+            entry.client,
+            Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+            entry.method,
+            entry.target,
+            entry.version,
+            entry.status,
+            entry.size.map_or_else(|| "-".to_string(), |size| size.to_string()),
+            escape(entry.referer.unwrap_or("-")),
+            escape(entry.user_agent.unwrap_or("-")),
+        );
+        let _ = self.lines.try_send(line);
+    }
+}
+
+async fn write_lines(mut writer: Box<dyn AsyncWrite + Send + Unpin>, mut pending: mpsc::Receiver<String>) {
+    while let Some(line) = pending.recv().await {
+        let written = writer.write_all(line.as_bytes()).await;
+        if let Err(e) = written.and(writer.flush().await) {
+            eprintln!("Failed to write access log: {}", e);
+        }
+    }
+}
+
+/// Keeps quoted fields on one line and unambiguous
+fn escape(field: &str) -> String {
+    field.replace('\\', "\\\\").replace('"', "\\\"").replace(['\n', '\r'], " ")
+}
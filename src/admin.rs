@@ -0,0 +1,81 @@
+use crate::metrics::Metrics;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+#[derive(Serialize)]
+struct GroupStatus {
+    group: &'static str,
+    enabled: bool,
+    metrics: usize,
+}
+
+#[derive(Deserialize)]
+struct GroupUpdate {
+    group: String,
+    enabled: bool,
+}
+
+pub(crate) fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_vec(value).unwrap()))
+        .unwrap()
+}
+
+pub(crate) fn text_response(status: StatusCode, text: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(format!("{}\n", text)))
+        .unwrap()
+}
+
+fn group_statuses(metrics: &Metrics) -> Vec<GroupStatus> {
+    metrics
+        .groups
+        .iter()
+        .map(|group| GroupStatus {
+            group: group.name,
+            enabled: group.enabled.load(Ordering::Relaxed),
+            metrics: metrics.group_metric_count(group),
+        })
+        .collect()
+}
+
+/// `GET /admin/groups` lists metric groups, `POST /admin/groups` with
+/// `{"group": "cache", "enabled": false}` toggles one without a restart
+/// when `writable` (`--enable-admin-api`)
+pub async fn groups_handler(
+    req: Request<Body>,
+    metrics: Arc<Metrics>,
+    writable: bool,
+) -> Result<Response<Body>, hyper::Error> {
+    match *req.method() {
+        Method::GET => Ok(json_response(StatusCode::OK, &group_statuses(&metrics))),
+        Method::POST if !writable => Ok(text_response(
+            StatusCode::FORBIDDEN,
+            "the admin API is read-only, start with --enable-admin-api",
+        )),
+        Method::POST => {
+            let body = hyper::body::to_bytes(req.into_body()).await?;
+            let update: GroupUpdate = match serde_json::from_slice(&body) {
+                Ok(update) => update,
+                Err(e) => return Ok(text_response(StatusCode::BAD_REQUEST, &e.to_string())),
+            };
+            if !metrics.set_group_enabled(&update.group, update.enabled) {
+                let message = format!("unknown group {}", update.group);
+                return Ok(text_response(StatusCode::NOT_FOUND, &message));
+            }
+            println!(
+                "Metric group {} {} via admin API",
+                update.group,
+                if update.enabled { "enabled" } else { "disabled" }
+            );
+            Ok(json_response(StatusCode::OK, &group_statuses(&metrics)))
+        }
+        _ => Ok(text_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")),
+    }
+}
@@ -0,0 +1,132 @@
+use crate::metrics;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Shortest time between two notifications of the same rule
+const DEBOUNCE: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+/// `[[alerts.rules]]` entry of the config file
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    /// uProf metric name, e.g. `amd_total_mem_bw_gbps`; compared in the
+    /// unit AMDuProfPcm reports regardless of `--rename-to-conventions`
+    pub metric: String,
+    pub threshold: f64,
+    pub comparison: Comparison,
+    /// Plain `http://` endpoint the alert is POSTed to
+    pub webhook_url: String,
+}
+
+#[derive(Serialize)]
+struct Notification<'a> {
+    nodename: &'a str,
+    metric: &'a str,
+    value: f64,
+    threshold: f64,
+    comparison: Comparison,
+}
+
+struct ArmedRule {
+    rule: AlertRule,
+    column: usize,
+    last_fired: Option<Instant>,
+}
+
+/// Webhooks are POSTed with a plain HTTP client, so an `https://` URL is
+/// refused here rather than failing on every alert
+pub fn validate(rules: &[AlertRule]) -> Result<(), String> {
+    match rules.iter().find(|rule| !rule.webhook_url.starts_with("http://")) {
+        Some(rule) => Err(format!("alert webhook {} is not an http:// URL, https is not supported", rule.webhook_url)),
+        None => Ok(()),
+    }
+}
+
+/// Checks every collected row against the configured rules and notifies
+/// their webhooks without holding up collection
+pub struct Alerts {
+    nodename: String,
+    rules: Vec<ArmedRule>,
+    client: Client<HttpConnector>,
+}
+
+impl Alerts {
+    pub fn new(rules: &[AlertRule], nodename: &str) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| {
+                let Some(column) = metrics::column_of(&rule.metric) else {
+                    eprintln!("Warning: alert rule for unknown metric {}", rule.metric);
+                    return None;
+                };
+                Some(ArmedRule {
+                    rule: rule.clone(),
+                    column,
+                    last_fired: None,
+                })
+            })
+            .collect();
+
+        Self {
+            nodename: nodename.to_string(),
+            rules,
+            client: Client::new(),
+        }
+    }
+
+    /// `values` is the row as exported, after transforms and bounds checks
+    pub fn evaluate(&mut self, values: &[f64]) {
+        for armed in &mut self.rules {
+            let value = values[armed.column];
+            let fires = match armed.rule.comparison {
+                Comparison::Above => value > armed.rule.threshold,
+                Comparison::Below => value < armed.rule.threshold,
+            };
+            if !fires || armed.last_fired.is_some_and(|fired| fired.elapsed() < DEBOUNCE) {
+                continue;
+            }
+            armed.last_fired = Some(Instant::now());
+
+            let body = serde_json::to_vec(&Notification {
+                nodename: &self.nodename,
+                metric: &armed.rule.metric,
+                value,
+                threshold: armed.rule.threshold,
+                comparison: armed.rule.comparison,
+            })
+            .unwrap();
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(&armed.rule.webhook_url)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body));
+            let request = match request {
+                Ok(request) => request,
+                Err(e) => {
+                    eprintln!("Invalid webhook URL {}: {}", armed.rule.webhook_url, e);
+                    continue;
+                }
+            };
+            let client = self.client.clone();
+            let url = armed.rule.webhook_url.clone();
+            tokio::spawn(async move {
+                match client.request(request).await {
+                    Ok(response) if !response.status().is_success() => {
+                        eprintln!("Alert webhook {} answered {}", url, response.status());
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Failed to notify alert webhook {}: {}", url, e),
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Exports AMD uProf counters in Prometheus format")]
+pub struct Args {
+    /// TOML configuration file
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Read performance counter MSRs directly when AMDuProfPcm is not installed (Zen 3/4, requires root)
+    #[arg(long)]
+    pub use_msr: bool,
+
+    /// Collect the same metrics through perf_event_open(2) when AMDuProfPcm is not installed
+    #[arg(long)]
+    pub use_perf: bool,
+
+    /// On Intel CPUs, collect memory and cache metrics with Intel PCM (`pcm`, `pcm-memory`)
+    #[arg(long)]
+    pub intel_fallback: bool,
+
+    /// Export local/remote NUMA page counts of this process
+    #[arg(long, value_name = "PID")]
+    pub trace_pid: Option<u32>,
+
+    /// Export uProf metrics under Prometheus-style names: `*_pti` becomes
+    /// `*_per_thousand_instructions` and `*_gbps` becomes `*_bytes_per_second`
+    #[arg(long)]
+    pub rename_to_conventions: bool,
+
+    /// With --rename-to-conventions, stop exporting the deprecated uProf names
+    /// alongside the new ones
+    #[arg(long, requires = "rename_to_conventions")]
+    pub drop_legacy_names: bool,
+
+    /// uProf metric groups to collect and export
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "cache,l3,memory",
+        value_parser = clap::builder::PossibleValuesParser::new(crate::metrics::GROUP_NAMES),
+    )]
+    pub enable_groups: Vec<String>,
+
+    /// Largest /metrics response in bytes; whole metric families beyond it are dropped
+    #[arg(long, value_name = "BYTES", default_value_t = 10 * 1024 * 1024)]
+    pub max_response_bytes: usize,
+
+    /// Kubernetes node labels to attach to every metric, e.g.
+    /// `topology.kubernetes.io/zone`; read from the API server, the service account needs `get` on nodes
+    #[arg(long, value_name = "LABELS", value_delimiter = ',')]
+    pub k8s_node_labels: Vec<String>,
+
+    /// Wait for AMDuProfPcm and PCM on a dedicated OS thread instead of the async runtime
+    #[arg(long)]
+    pub subprocess_thread: bool,
+
+    /// CPUs for the collector thread and the AMDuProfPcm it spawns, e.g. `0,2,4`;
+    /// implies --subprocess-thread
+    #[arg(long, value_name = "CPUS", value_delimiter = ',')]
+    pub collector_cpu_affinity: Vec<usize>,
+
+    /// SCHED_FIFO priority of the collector thread and the tools it runs;
+    /// needs CAP_SYS_NICE and implies --subprocess-thread
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(i32).range(1..=99))]
+    pub rt_priority: Option<i32>,
+
+    /// Delay the first collection by a random share of the interval, up to this percentage
+    #[arg(long, value_name = "N", default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub jitter_percent: u8,
+
+    /// Collect only while this cron expression matches, e.g. `"*/5 9-17 * * 1-5"`;
+    /// five fields select whole minutes, six or seven start with seconds
+    #[arg(long, value_name = "CRON")]
+    pub schedule: Option<String>,
+
+    /// Concurrent AMDuProfPcm runs, each pinned to its share of the sockets
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub workers: usize,
+
+    /// Longest time /metrics/live waits for its collection
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    pub collection_timeout_secs: u64,
+
+    /// What plain /metrics serves: the cached result or a fresh collection
+    #[arg(long, value_enum, default_value_t = ScrapeMode::Cached)]
+    pub default_mode: ScrapeMode,
+
+    /// Script run before each collection; a `phase=<value>` line on its stdout
+    /// becomes a `phase` label on all metrics of that cycle
+    #[arg(long, value_name = "PATH")]
+    pub pre_collect_hook: Option<PathBuf>,
+
+    /// Time the pre-collect hook may take before collection goes on without it
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    pub pre_collect_hook_timeout_ms: u64,
+
+    /// Constant labels added to every metric, e.g. `datacenter=fra1,rack=r12`
+    #[arg(
+        long,
+        value_name = "NAME=VALUE",
+        value_delimiter = ',',
+        value_parser = crate::label::parse_label_pair,
+    )]
+    pub extra_labels: Vec<(String, String)>,
+
+    /// Reverse proxies whose `X-Forwarded-For` header is trusted for the client address
+    #[arg(long, value_name = "CIDRS", value_delimiter = ',')]
+    pub trusted_proxy_cidrs: Vec<crate::proxy::Cidr>,
+
+    /// Write a Combined Log Format access log to this file, or to `stdout`
+    #[arg(long, value_name = "PATH")]
+    pub access_log: Option<String>,
+
+    /// Oldest cached result /metrics/live falls back to when its own collection fails
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    pub max_staleness_secs: u64,
+
+    /// Addresses to serve on, e.g. only the management interface or `[::]:9100`
+    #[arg(long, value_name = "IP:PORT", value_delimiter = ',', default_value = "0.0.0.0:9100")]
+    pub listen_addrs: Vec<std::net::SocketAddr>,
+
+    /// Accept only IPv6 connections on IPv6 listen addresses such as `[::]:9100`
+    #[arg(long)]
+    pub ipv6_only: bool,
+
+    /// Allow `POST /admin/groups` to enable and disable metric groups; without
+    /// it the groups can only be listed
+    #[arg(long)]
+    pub enable_admin_api: bool,
+
+    /// Prometheus web config (YAML) with `tls_server_config` and `basic_auth_users`
+    #[arg(long = "web.config.file", value_name = "PATH")]
+    pub web_config_file: Option<PathBuf>,
+
+    /// Start serving without the initial collection that checks the data source works
+    #[arg(long)]
+    pub skip_startup_test: bool,
+
+    /// Text AMDuProfPcm --version must print for the binary to be trusted
+    #[arg(long, value_name = "TEXT", default_value = "AMDuProf")]
+    pub binary_version_match: String,
+
+    /// Run AMDuProfPcm without checking its --version output
+    #[arg(long)]
+    pub skip_binary_validation: bool,
+
+    /// AMDuProfPcm report path, strftime specifiers give every run its own file
+    #[arg(
+        long,
+        value_name = "PATH",
+        default_value = "/var/uprof/uprof_metrics.csv",
+        value_parser = crate::output::parse_template,
+    )]
+    pub output_path_template: String,
+
+    /// Age after which reports left behind by failed runs are deleted
+    #[arg(long, value_name = "SECONDS", default_value_t = 3600)]
+    pub output_max_age_secs: u64,
+
+    /// Move read reports into this directory instead of deleting them
+    #[arg(long, value_name = "DIR")]
+    pub archive_dir: Option<PathBuf>,
+
+    /// Reports kept in --archive-dir, the oldest are deleted first
+    #[arg(long, default_value_t = 10, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub archive_count: usize,
+
+    /// Check each report against the SHA-256 in its `.sha256` sidecar file
+    #[arg(long)]
+    pub verify_checksum: bool,
+
+    /// Run one AMDuProfPcm per enabled metric group at the same time; runs
+    /// through a subprocess thread still happen one after another
+    #[arg(long)]
+    pub parallel_profiles: bool,
+
+    /// Only set gauges whose value changed by more than --delta-epsilon
+    #[arg(long)]
+    pub delta_parse: bool,
+
+    /// Smallest change --delta-parse passes on to a gauge
+    #[arg(long, default_value_t = 1e-6)]
+    pub delta_epsilon: f64,
+
+    /// Memory-map AMDuProfPcm reports instead of reading them onto the heap
+    #[arg(long)]
+    pub mmap_output: bool,
+
+    /// Register each uProf gauge only once it has had a value
+    #[arg(long)]
+    pub lazy_registration: bool,
+
+    /// Unregister uProf gauges that read zero this many collections in a
+    /// row, until they read something else
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub prune_zero_metrics: Option<u32>,
+
+    /// Write the /snapshot JSON to this file after every collection
+    #[arg(long, value_name = "PATH")]
+    pub snapshot_path: Option<PathBuf>,
+
+    /// Serve the values of a --snapshot-path file until the first collection;
+    /// mostly useful with --skip-startup-test
+    #[arg(long, value_name = "PATH")]
+    pub restore_snapshot: Option<PathBuf>,
+
+    /// Peak memory bandwidth for amd_mem_bw_utilization_percent, detected
+    /// from the SMBIOS DIMM entries when left out
+    #[arg(long, value_name = "GBPS")]
+    pub max_mem_bw_gbps: Option<f64>,
+
+    /// Export the CPU usage of this many processes that used the most CPU
+    /// time since the previous collection
+    #[arg(long, value_name = "N")]
+    pub top_pid_count: Option<usize>,
+
+    /// Export the traffic of the loopback interface along with the others
+    #[arg(long)]
+    pub net_include_loopback: bool,
+
+    /// Export the read and write system calls summed over /proc/<pid>/io
+    #[arg(long)]
+    pub process_syscalls: bool,
+
+    /// Collection intervals without a successful collection after which
+    /// amd_uprof_watchdog drops to 0
+    #[arg(long, value_name = "N", default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    pub watchdog_intervals: u32,
+
+    /// Smallest change in percent /metrics/diff reports
+    #[arg(long, value_name = "PERCENT", default_value_t = 1.0)]
+    pub diff_threshold_percent: f64,
+
+    /// Request header carrying a trace ID, e.g. X-Trace-Id, attached as an
+    /// exemplar to amd_l3_miss_percent and amd_total_mem_bw_gbps in
+    /// OpenMetrics scrapes
+    #[arg(long, value_name = "HEADER")]
+    pub tracing_header: Option<String>,
+
+    /// YAML file of tenants, each with a bearer token and the nodename
+    /// patterns its scrapes are filtered to
+    #[arg(long, value_name = "FILE")]
+    pub tenant_config: Option<PathBuf>,
+
+    /// Serve the merged metrics of other exporters listed in --upstream-urls
+    /// instead of running AMDuProfPcm
+    #[arg(long, requires = "upstream_urls")]
+    pub federation_mode: bool,
+
+    /// Exporters scraped in --federation-mode, e.g. `http://node-1:9100`
+    #[arg(long, value_name = "URLS", value_delimiter = ',')]
+    pub upstream_urls: Vec<String>,
+
+    /// Where the nodename label comes from, tried in order: `env`
+    /// (HOST_HOSTNAME), `host_hostname_file` (/host_hostname), `etc_file`
+    /// (/etc/host_hostname), `hostname_cmd` and `literal:<value>`
+    #[arg(
+        long,
+        value_name = "SOURCES",
+        value_delimiter = ',',
+        default_value = "env,host_hostname_file,etc_file,hostname_cmd"
+    )]
+    pub nodename_source: Vec<NodenameSource>,
+
+    /// Use this nodename and skip the hostname detection
+    #[arg(
+        long,
+        value_name = "HOSTNAME",
+        conflicts_with = "nodename_source",
+        value_parser = clap::builder::NonEmptyStringValueParser::new()
+    )]
+    pub nodename: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ScrapeMode {
+    /// Last collected result, rendered on demand until the first collection
+    Cached,
+    /// Run a collection for the scrape, like /metrics/live
+    Live,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodenameSource {
+    Env,
+    HostHostnameFile,
+    EtcFile,
+    HostnameCmd,
+    Literal(String),
+}
+
+impl FromStr for NodenameSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "env" => Ok(Self::Env),
+            "host_hostname_file" => Ok(Self::HostHostnameFile),
+            "etc_file" => Ok(Self::EtcFile),
+            "hostname_cmd" => Ok(Self::HostnameCmd),
+            _ => match s.strip_prefix("literal:") {
+                Some(value) if !value.is_empty() => Ok(Self::Literal(value.to_string())),
+                _ => Err(format!(
+                    "unknown nodename source {}, expected env, host_hostname_file, etc_file, hostname_cmd or literal:<value>",
+                    s
+                )),
+            },
+        }
+    }
+}
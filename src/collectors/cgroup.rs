@@ -0,0 +1,70 @@
+use super::{read_f64, read_key_values, Collector};
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+
+const CGROUP: &str = "/sys/fs/cgroup";
+
+/// Resource usage of the exporter's own cgroup v2, which bounds the CPU
+/// time AMDuProfPcm gets inside a container
+pub struct CgroupCollector {
+    nodename: String,
+    cpu_throttled: GaugeVec,
+    cpu_throttle_events: GaugeVec,
+    memory_usage: GaugeVec,
+    memory_limit: GaugeVec,
+}
+
+impl CgroupCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let gauge = |name: &str, help: &str| {
+            let gauge = GaugeVec::new(Opts::new(name, help), &["nodename"]).unwrap();
+            registry.register(&gauge);
+            gauge
+        };
+
+        Self {
+            nodename: nodename.to_string(),
+            cpu_throttled: gauge(
+                "amd_container_cpu_throttled_usec_total",
+                "Total time the container was throttled by its CPU quota, in microseconds",
+            ),
+            cpu_throttle_events: gauge(
+                "amd_container_cpu_throttle_events_total",
+                "Periods in which the container used up its CPU quota",
+            ),
+            memory_usage: gauge("amd_container_memory_usage_bytes", "Memory charged to the container"),
+            memory_limit: gauge(
+                "amd_container_memory_limit_bytes",
+                "Memory limit of the container, absent when unlimited",
+            ),
+        }
+    }
+}
+
+impl Collector for CgroupCollector {
+    fn name(&self) -> &'static str {
+        "cgroup"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        let labels = [self.nodename.as_str()];
+
+        // Not running under cgroup v2: nothing to report
+        if let Ok(stat) = read_key_values(format!("{}/cpu.stat", CGROUP)) {
+            if let Some(throttled) = stat.get("throttled_usec") {
+                self.cpu_throttled.with_label_values(&labels).set(*throttled);
+            }
+            if let Some(events) = stat.get("nr_throttled") {
+                self.cpu_throttle_events.with_label_values(&labels).set(*events);
+            }
+        }
+        if let Some(usage) = read_f64(format!("{}/memory.current", CGROUP)) {
+            self.memory_usage.with_label_values(&labels).set(usage);
+        }
+        // memory.max reads "max" without a limit
+        if let Some(limit) = read_f64(format!("{}/memory.max", CGROUP)) {
+            self.memory_limit.with_label_values(&labels).set(limit);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+use super::{entries_with_prefix, read_f64, read_trimmed, Collector};
+use crate::label::sanitize_label_value;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+
+const CPU_DIR: &str = "/sys/devices/system/cpu";
+
+/// Frequency scaling governor per core and global boost state
+pub struct CpuFreqCollector {
+    nodename: String,
+    governor: GaugeVec,
+    boost: GaugeVec,
+}
+
+impl CpuFreqCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let governor = GaugeVec::new(
+            Opts::new("amd_cpu_governor_info", "Scaling governor of the core"),
+            &["nodename", "core", "governor"]
+        ).unwrap();
+        let boost = GaugeVec::new(
+            Opts::new("amd_cpu_boost_enabled", "1 if frequency boost is enabled"),
+            &["nodename"]
+        ).unwrap();
+
+        registry.register(&governor);
+        registry.register(&boost);
+
+        Self {
+            nodename: nodename.to_string(),
+            governor,
+            boost,
+        }
+    }
+}
+
+impl Collector for CpuFreqCollector {
+    fn name(&self) -> &'static str {
+        "cpufreq"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        self.governor.reset();
+        for (name, path) in entries_with_prefix(CPU_DIR, "cpu") {
+            let Some(core) = name.strip_prefix("cpu").filter(|n| n.parse::<u32>().is_ok()) else {
+                continue;
+            };
+            if let Some(governor) = read_trimmed(path.join("cpufreq/scaling_governor")) {
+                self.governor
+                    .with_label_values(&[&self.nodename, core, &sanitize_label_value(&governor)])
+                    .set(1.0);
+            }
+        }
+
+        if let Some(boost) = read_f64(format!("{}/cpufreq/boost", CPU_DIR)) {
+            self.boost.with_label_values(&[&self.nodename]).set(boost);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,127 @@
+use super::Collector;
+use crate::label::sanitize_label_value;
+use crate::perf::{pmu_config, uncore_pmu, Counter};
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use serde::Deserialize;
+use std::io;
+use std::time::Instant;
+
+/// Data Fabric event as listed in the PPR of the processor family
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DfEventConfig {
+    pub event: u64,
+    pub umask: u64,
+}
+
+/// `config` of the event in the `amd_df` PMU's own format
+fn df_config(event: DfEventConfig) -> io::Result<u64> {
+    pmu_config("amd_df", &[("event", event.event), ("umask", event.umask)])
+}
+
+pub fn default_bytes_per_beat() -> f64 {
+    64.0
+}
+
+/// Read and write DF events of one exported series
+pub struct DfTraffic {
+    pub labels: Vec<String>,
+    pub read: DfEventConfig,
+    pub write: DfEventConfig,
+    pub bytes_per_beat: f64,
+}
+
+struct Counted {
+    labels: Vec<String>,
+    bytes_per_beat: f64,
+    /// One counter per Data Fabric instance, summed
+    read: Vec<Counter>,
+    write: Vec<Counter>,
+}
+
+/// Read and write bandwidth counted by configured `amd_df` perf events
+pub struct DfBandwidthCollector {
+    name: &'static str,
+    nodename: String,
+    read: GaugeVec,
+    write: GaugeVec,
+    traffic: Vec<Counted>,
+    last: Instant,
+}
+
+impl DfBandwidthCollector {
+    /// `read` and `write` are the (name, help) of the gauges, labelled with
+    /// `nodename` followed by `labels`
+    pub fn new(
+        name: &'static str,
+        registry: &Registrar,
+        nodename: &str,
+        read: (&str, &str),
+        write: (&str, &str),
+        labels: &[&str],
+        traffic: Vec<DfTraffic>,
+    ) -> io::Result<Self> {
+        let (pmu, cpus) = uncore_pmu("amd_df")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no amd_df perf PMU"))?;
+        let open = |event: DfEventConfig| -> io::Result<Vec<Counter>> {
+            cpus.iter()
+                .map(|&cpu| Counter::open(name, pmu, df_config(event)?, cpu))
+                .collect()
+        };
+        let traffic = traffic
+            .into_iter()
+            .map(|traffic| {
+                Ok(Counted {
+                    labels: traffic.labels.iter().map(|label| sanitize_label_value(label)).collect(),
+                    bytes_per_beat: traffic.bytes_per_beat,
+                    read: open(traffic.read)?,
+                    write: open(traffic.write)?,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let labels: Vec<&str> = std::iter::once("nodename").chain(labels.iter().copied()).collect();
+        let read = GaugeVec::new(Opts::new(read.0, read.1), &labels).unwrap();
+        let write = GaugeVec::new(Opts::new(write.0, write.1), &labels).unwrap();
+        registry.register(&read);
+        registry.register(&write);
+
+        Ok(Self {
+            name,
+            nodename: nodename.to_string(),
+            read,
+            write,
+            traffic,
+            last: Instant::now(),
+        })
+    }
+}
+
+impl Collector for DfBandwidthCollector {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn collect(&mut self) -> io::Result<()> {
+        let elapsed = self.last.elapsed().as_secs_f64();
+        self.last = Instant::now();
+        for traffic in &mut self.traffic {
+            let gbps = |counters: &mut [Counter]| -> io::Result<f64> {
+                let mut beats = 0.0;
+                for counter in counters {
+                    beats += counter.delta()?;
+                }
+                Ok(beats * traffic.bytes_per_beat / elapsed / 1e9)
+            };
+            let read = gbps(&mut traffic.read)?;
+            let write = gbps(&mut traffic.write)?;
+            let labels: Vec<&str> = std::iter::once(self.nodename.as_str())
+                .chain(traffic.labels.iter().map(String::as_str))
+                .collect();
+            self.read.with_label_values(&labels).set(read);
+            self.write.with_label_values(&labels).set(write);
+        }
+        Ok(())
+    }
+}
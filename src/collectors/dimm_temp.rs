@@ -0,0 +1,64 @@
+use super::{entries_with_prefix, read_f64, read_trimmed, Collector};
+use crate::label::sanitize_label_value;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+
+const I2C_DEVICES: &str = "/sys/bus/i2c/devices";
+/// Drivers of the JEDEC thermal sensors on DDR4 (JC-42.4) and DDR5 (SPD5118) DIMMs
+const SENSOR_DRIVERS: [&str; 2] = ["jc42", "spd5118"];
+
+/// Temperature of every DIMM with an SPD thermal sensor, keyed by its I2C
+/// device, e.g. `0-0018`
+pub fn dimm_temperatures() -> Vec<(String, f64)> {
+    entries_with_prefix(I2C_DEVICES, "")
+        .into_iter()
+        .filter(|(_, path)| {
+            read_trimmed(path.join("name")).is_some_and(|name| SENSOR_DRIVERS.contains(&name.as_str()))
+        })
+        .filter_map(|(device, path)| {
+            // Older kernels put the attribute on the device itself
+            let millidegrees = read_f64(path.join("temp1_input")).or_else(|| {
+                entries_with_prefix(path.join("hwmon"), "hwmon")
+                    .into_iter()
+                    .find_map(|(_, hwmon)| read_f64(hwmon.join("temp1_input")))
+            })?;
+            Some((device, millidegrees / 1000.0))
+        })
+        .collect()
+}
+
+/// DIMM temperatures, which rise with sustained memory bandwidth
+pub struct DimmTempCollector {
+    nodename: String,
+    temperature: GaugeVec,
+}
+
+impl DimmTempCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let temperature = GaugeVec::new(
+            Opts::new("amd_dimm_temperature_celsius", "Temperature of the DIMM's SPD thermal sensor"),
+            &["nodename", "slot"]
+        ).unwrap();
+        registry.register(&temperature);
+
+        Self {
+            nodename: nodename.to_string(),
+            temperature,
+        }
+    }
+}
+
+impl Collector for DimmTempCollector {
+    fn name(&self) -> &'static str {
+        "dimm_temp"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        for (slot, celsius) in dimm_temperatures() {
+            self.temperature
+                .with_label_values(&[&self.nodename, &sanitize_label_value(&slot)])
+                .set(celsius);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use super::Collector;
+use crate::dmi::{self, MemoryDevice};
+use crate::label::sanitize_label_value;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+
+/// Installed DIMMs from the SMBIOS table, so that bandwidth can be compared
+/// between nodes with the same memory configuration
+pub struct DmiCollector {
+    nodename: String,
+    info: GaugeVec,
+    /// Read once at startup, DIMMs do not change while the host runs
+    devices: Vec<MemoryDevice>,
+}
+
+impl DmiCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let info = GaugeVec::new(
+            Opts::new("amd_dimm_info", "Populated DIMM slot"),
+            &["nodename", "slot", "size_mb", "speed_mhz", "type"]
+        ).unwrap();
+        registry.register(&info);
+
+        Self {
+            nodename: nodename.to_string(),
+            info,
+            // Unreadable without root; the peak bandwidth detection warns about it
+            devices: dmi::memory_devices().unwrap_or_default(),
+        }
+    }
+}
+
+impl Collector for DmiCollector {
+    fn name(&self) -> &'static str {
+        "dmi"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        for device in &self.devices {
+            let speed = device.speed_mts.map(|speed| speed.to_string()).unwrap_or_default();
+            self.info
+                .with_label_values(&[
+                    &self.nodename,
+                    &sanitize_label_value(&device.locator),
+                    &device.size_mb.to_string(),
+                    &speed,
+                    device.memory_type,
+                ])
+                .set(1.0);
+        }
+        Ok(())
+    }
+}
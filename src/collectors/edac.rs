@@ -0,0 +1,82 @@
+use super::{entries_with_prefix, read_f64, Collector};
+use crate::label::sanitize_label_value;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const DRIVER_DIR: &str = "/sys/bus/platform/drivers/amd64_edac";
+const EDAC_DIR: &str = "/sys/devices/system/edac";
+
+/// ECC error counts of the memory controllers driven by `amd64_edac`
+pub struct EdacCollector {
+    nodename: String,
+    uncorrectable: GaugeVec,
+    correctable: GaugeVec,
+}
+
+impl EdacCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let labels = &["nodename", "mc", "csrow", "channel"];
+        let uncorrectable = GaugeVec::new(
+            Opts::new("amd_edac_uncorrectable_errors_total", "EDAC uncorrectable memory errors"),
+            labels
+        ).unwrap();
+        let correctable = GaugeVec::new(
+            Opts::new("amd_edac_correctable_errors_total", "EDAC correctable memory errors"),
+            labels
+        ).unwrap();
+
+        registry.register(&uncorrectable);
+        registry.register(&correctable);
+
+        Self {
+            nodename: nodename.to_string(),
+            uncorrectable,
+            correctable,
+        }
+    }
+
+    /// `mc*` directories under the driver's devices, falling back to the
+    /// generic EDAC tree
+    fn controllers() -> BTreeMap<String, PathBuf> {
+        let mut controllers = BTreeMap::new();
+        for (_, device) in entries_with_prefix(DRIVER_DIR, "") {
+            controllers.extend(entries_with_prefix(device, "mc"));
+        }
+        for (name, path) in entries_with_prefix(format!("{}/mc", EDAC_DIR), "mc") {
+            controllers.entry(name).or_insert(path);
+        }
+        controllers
+    }
+}
+
+impl Collector for EdacCollector {
+    fn name(&self) -> &'static str {
+        "edac"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        for (mc, mc_path) in Self::controllers() {
+            let mc = sanitize_label_value(&mc);
+            for (csrow, csrow_path) in entries_with_prefix(&mc_path, "csrow") {
+                let csrow = sanitize_label_value(&csrow);
+                if let Some(count) = read_f64(csrow_path.join("ue_count")) {
+                    self.uncorrectable
+                        .with_label_values(&[&self.nodename, &mc, &csrow, ""])
+                        .set(count);
+                }
+                for (file, path) in entries_with_prefix(&csrow_path, "ch") {
+                    if let Some(channel) = file.strip_suffix("_ce_count") {
+                        if let Some(count) = read_f64(path) {
+                            self.correctable
+                                .with_label_values(&[&self.nodename, &mc, &csrow, &sanitize_label_value(channel)])
+                                .set(count);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
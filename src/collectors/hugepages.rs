@@ -0,0 +1,67 @@
+use super::{read_key_values, Collector};
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+
+const MEMINFO: &str = "/proc/meminfo";
+
+const MEMINFO_FIELDS: &[(&str, &str, &str)] = &[
+    ("HugePages_Total", "amd_hugepages_total", "Huge pages in the pool"),
+    ("HugePages_Free", "amd_hugepages_free", "Huge pages not yet allocated"),
+    ("HugePages_Rsvd", "amd_hugepages_rsvd", "Huge pages reserved but not yet faulted in"),
+    ("HugePages_Surp", "amd_hugepages_surp", "Surplus huge pages above the configured pool size"),
+];
+
+/// Explicit huge page pool usage
+pub struct HugePagesCollector {
+    nodename: String,
+    gauges: Vec<(&'static str, GaugeVec)>,
+    info: GaugeVec,
+}
+
+impl HugePagesCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let gauges: Vec<(&'static str, GaugeVec)> = MEMINFO_FIELDS
+            .iter()
+            .map(|&(field, name, help)| {
+                let gauge = GaugeVec::new(Opts::new(name, help), &["nodename"]).unwrap();
+                registry.register(&gauge);
+                (field, gauge)
+            })
+            .collect();
+
+        let info = GaugeVec::new(
+            Opts::new("amd_hugepages_info", "Default huge page size"),
+            &["nodename", "hugepagesize"]
+        ).unwrap();
+        registry.register(&info);
+
+        Self {
+            nodename: nodename.to_string(),
+            gauges,
+            info,
+        }
+    }
+}
+
+impl Collector for HugePagesCollector {
+    fn name(&self) -> &'static str {
+        "hugepages"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        let meminfo = read_key_values(MEMINFO)?;
+        for (field, gauge) in &self.gauges {
+            if let Some(value) = meminfo.get(*field) {
+                gauge.with_label_values(&[&self.nodename]).set(*value);
+            }
+        }
+
+        if let Some(size) = meminfo.get("Hugepagesize") {
+            self.info.reset();
+            self.info
+                .with_label_values(&[&self.nodename, &format!("{}kB", size)])
+                .set(1.0);
+        }
+        Ok(())
+    }
+}
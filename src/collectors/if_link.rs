@@ -0,0 +1,37 @@
+use super::df::{default_bytes_per_beat, DfBandwidthCollector, DfEventConfig, DfTraffic};
+use crate::registration::Registrar;
+use serde::Deserialize;
+
+/// `[[if_links]]` entry of the config file: the DF events counting the
+/// data beats of one Infinity Fabric link
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct IfLinkConfig {
+    pub link_id: String,
+    pub read: DfEventConfig,
+    pub write: DfEventConfig,
+    #[serde(default = "default_bytes_per_beat")]
+    pub bytes_per_beat: f64,
+}
+
+/// Infinity Fabric link bandwidth counted by the `amd_df` perf PMU
+pub fn collector(registry: &Registrar, nodename: &str, links: &[IfLinkConfig]) -> std::io::Result<DfBandwidthCollector> {
+    let traffic = links
+        .iter()
+        .map(|link| DfTraffic {
+            labels: vec![link.link_id.clone()],
+            read: link.read,
+            write: link.write,
+            bytes_per_beat: link.bytes_per_beat,
+        })
+        .collect();
+    DfBandwidthCollector::new(
+        "if_link",
+        registry,
+        nodename,
+        ("amd_if_link_read_gbps", "Infinity Fabric link read bandwidth (GB/s)"),
+        ("amd_if_link_write_gbps", "Infinity Fabric link write bandwidth (GB/s)"),
+        &["link_id"],
+        traffic,
+    )
+}
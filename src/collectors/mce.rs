@@ -0,0 +1,101 @@
+use super::Collector;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::AsRawFd;
+
+const MCELOG: &str = "/dev/mcelog";
+
+// _IOR('M', 1, int) and _IOR('M', 2, int) from asm/mce.h
+const MCE_GET_RECORD_LEN: libc::c_ulong = 0x8004_4d01;
+const MCE_GET_LOG_LEN: libc::c_ulong = 0x8004_4d02;
+
+// Offsets inside `struct mce`
+const STATUS_OFFSET: usize = 0;
+const BANK_OFFSET: usize = 65;
+const MCI_STATUS_UC: u64 = 1 << 61;
+
+struct Mcelog {
+    file: File,
+    record_len: usize,
+    log_len: usize,
+}
+
+impl Mcelog {
+    fn open() -> std::io::Result<Self> {
+        let file = File::open(MCELOG)?;
+        let record_len = Self::ioctl(&file, MCE_GET_RECORD_LEN)?;
+        let log_len = Self::ioctl(&file, MCE_GET_LOG_LEN)?;
+        if record_len <= BANK_OFFSET {
+            return Err(std::io::Error::other(format!("unexpected MCE record length {}", record_len)));
+        }
+        Ok(Self { file, record_len, log_len })
+    }
+
+    fn ioctl(file: &File, request: libc::c_ulong) -> std::io::Result<usize> {
+        let mut value: libc::c_int = 0;
+        if unsafe { libc::ioctl(file.as_raw_fd(), request, &mut value) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(value as usize)
+    }
+}
+
+/// Counts machine check events drained from `/dev/mcelog`.
+///
+/// Reading the device consumes the records, so this should not run next to
+/// an `mcelog` daemon.
+pub struct MceCollector {
+    nodename: String,
+    events: GaugeVec,
+    mcelog: Option<Mcelog>,
+}
+
+impl MceCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let events = GaugeVec::new(
+            Opts::new("amd_mce_events_total", "Machine check events"),
+            &["nodename", "bank", "type"]
+        ).unwrap();
+        registry.register(&events);
+
+        let mcelog = match Mcelog::open() {
+            Ok(mcelog) => Some(mcelog),
+            Err(e) => {
+                eprintln!("Warning: {} is not available, MCE events will not be collected: {}", MCELOG, e);
+                None
+            }
+        };
+
+        Self {
+            nodename: nodename.to_string(),
+            events,
+            mcelog,
+        }
+    }
+}
+
+impl Collector for MceCollector {
+    fn name(&self) -> &'static str {
+        "mce"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        let Some(mcelog) = &mut self.mcelog else {
+            return Ok(());
+        };
+
+        // The kernel only accepts reads of the whole log
+        let mut buf = vec![0u8; mcelog.record_len * mcelog.log_len];
+        let read = mcelog.file.read(&mut buf)?;
+
+        for record in buf[..read].chunks_exact(mcelog.record_len) {
+            let status = u64::from_ne_bytes(record[STATUS_OFFSET..STATUS_OFFSET + 8].try_into().unwrap());
+            let bank = record[BANK_OFFSET].to_string();
+            let kind = if status & MCI_STATUS_UC != 0 { "uncorrected" } else { "corrected" };
+            self.events.with_label_values(&[&self.nodename, &bank, kind]).inc();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! Host metrics gathered from procfs/sysfs next to the uProf counters.
+
+use crate::cli::Args;
+use crate::config::Config;
+use crate::registration::Registrar;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub mod cgroup;
+pub mod cpufreq;
+pub mod df;
+pub mod dimm_temp;
+pub mod dmi;
+pub mod edac;
+pub mod hugepages;
+pub mod if_link;
+pub mod mce;
+pub mod netdev;
+pub mod numa;
+pub mod numa_maps;
+pub mod oom;
+pub mod pcie;
+pub mod psi;
+pub mod rapl;
+pub mod sched;
+pub mod stat;
+pub mod thermal;
+pub mod thp;
+pub mod top_processes;
+pub mod vmstat;
+
+/// A set of gauges refreshed on every collection interval
+pub trait Collector: Send {
+    fn name(&self) -> &'static str;
+
+    fn collect(&mut self) -> std::io::Result<()>;
+}
+
+pub fn default_collectors(
+    args: &Args,
+    config: &Config,
+    registry: &Registrar,
+    nodename: &str,
+) -> Vec<Box<dyn Collector>> {
+    let mut collectors: Vec<Box<dyn Collector>> = vec![
+        Box::new(edac::EdacCollector::new(registry, nodename)),
+        Box::new(mce::MceCollector::new(registry, nodename)),
+        Box::new(thp::ThpCollector::new(registry, nodename)),
+        Box::new(hugepages::HugePagesCollector::new(registry, nodename)),
+        Box::new(thermal::ThermalCollector::new(registry, nodename)),
+        Box::new(cpufreq::CpuFreqCollector::new(registry, nodename)),
+        Box::new(numa::NumaMemCollector::new(registry, nodename)),
+        Box::new(cgroup::CgroupCollector::new(registry, nodename)),
+        Box::new(dmi::DmiCollector::new(registry, nodename)),
+        Box::new(dimm_temp::DimmTempCollector::new(registry, nodename)),
+        Box::new(rapl::RaplCollector::new(registry, nodename)),
+        Box::new(netdev::NetDevCollector::new(registry, nodename, args.net_include_loopback)),
+        Box::new(vmstat::VmstatCollector::new(registry, nodename)),
+        Box::new(sched::SchedCollector::new(registry, nodename)),
+        Box::new(oom::OomCollector::new(registry, nodename)),
+        Box::new(psi::PsiCollector::new(registry, nodename)),
+        Box::new(stat::StatCollector::new(registry, nodename, args.process_syscalls)),
+    ];
+
+    if let Some(pid) = args.trace_pid {
+        collectors.push(Box::new(numa_maps::NumaMapsCollector::new(registry, nodename, pid)));
+    }
+
+    if let Some(count) = args.top_pid_count {
+        collectors.push(Box::new(top_processes::TopProcessesCollector::new(registry, nodename, count)));
+    }
+
+    if !config.if_links.is_empty() {
+        match if_link::collector(registry, nodename, &config.if_links) {
+            Ok(collector) => collectors.push(Box::new(collector)),
+            Err(e) => eprintln!("Warning: Infinity Fabric link metrics will not be collected: {}", e),
+        }
+    }
+
+    if !config.pcie.is_empty() {
+        match pcie::collector(registry, nodename, &config.pcie) {
+            Ok(collector) => collectors.push(Box::new(collector)),
+            Err(e) => eprintln!("Warning: PCIe metrics will not be collected: {}", e),
+        }
+    }
+
+    collectors
+}
+
+fn read_trimmed(path: impl AsRef<Path>) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+fn read_f64(path: impl AsRef<Path>) -> Option<f64> {
+    read_trimmed(path)?.parse().ok()
+}
+
+/// Parses `key value` lines as found in `/proc/vmstat`; a trailing colon on
+/// the key and anything after the value (e.g. `kB` in `/proc/meminfo`) are
+/// ignored
+pub(crate) fn read_key_values(path: impl AsRef<Path>) -> std::io::Result<HashMap<String, f64>> {
+    let content = fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let key = fields.next()?.trim_end_matches(':');
+            let value = fields.next()?.parse().ok()?;
+            Some((key.to_string(), value))
+        })
+        .collect())
+}
+
+/// Entries of `dir` whose names start with `prefix`, as (name, path) pairs.
+/// A missing directory yields nothing.
+fn entries_with_prefix(dir: impl AsRef<Path>, prefix: &str) -> Vec<(String, PathBuf)> {
+    let mut entries: Vec<(String, PathBuf)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            name.starts_with(prefix).then(|| (name, entry.path()))
+        })
+        .collect();
+    entries.sort();
+    entries
+}
@@ -0,0 +1,85 @@
+use super::Collector;
+use crate::label::sanitize_label_value;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use std::collections::HashMap;
+use std::fs;
+use std::time::Instant;
+
+const NET_DEV: &str = "/proc/net/dev";
+
+/// Network traffic per interface, whose DMA shows up as memory bandwidth
+pub struct NetDevCollector {
+    nodename: String,
+    include_loopback: bool,
+    rx_total: GaugeVec,
+    tx_total: GaugeVec,
+    rx_rate: GaugeVec,
+    tx_rate: GaugeVec,
+    /// (rx, tx) bytes of every interface at the previous read
+    last: HashMap<String, (f64, f64)>,
+    last_read: Instant,
+}
+
+/// (interface, rx bytes, tx bytes) of every line of `/proc/net/dev`
+fn parse_net_dev(content: &str) -> Vec<(&str, f64, f64)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (interface, counters) = line.split_once(':')?;
+            let fields: Vec<&str> = counters.split_whitespace().collect();
+            Some((interface.trim(), fields.first()?.parse().ok()?, fields.get(8)?.parse().ok()?))
+        })
+        .collect()
+}
+
+impl NetDevCollector {
+    pub fn new(registry: &Registrar, nodename: &str, include_loopback: bool) -> Self {
+        let gauge = |name: &str, help: &str| {
+            let gauge = GaugeVec::new(Opts::new(name, help), &["nodename", "interface"]).unwrap();
+            registry.register(&gauge);
+            gauge
+        };
+        Self {
+            nodename: nodename.to_string(),
+            include_loopback,
+            rx_total: gauge("amd_net_rx_bytes_total", "Bytes received by the interface"),
+            tx_total: gauge("amd_net_tx_bytes_total", "Bytes sent by the interface"),
+            rx_rate: gauge("amd_net_rx_bytes_per_second", "Bytes received since the previous collection, per second"),
+            tx_rate: gauge("amd_net_tx_bytes_per_second", "Bytes sent since the previous collection, per second"),
+            last: HashMap::new(),
+            last_read: Instant::now(),
+        }
+    }
+}
+
+impl Collector for NetDevCollector {
+    fn name(&self) -> &'static str {
+        "netdev"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        let content = fs::read_to_string(NET_DEV)?;
+        let elapsed = self.last_read.elapsed().as_secs_f64();
+        self.last_read = Instant::now();
+
+        let mut current = HashMap::new();
+        for (interface, rx, tx) in parse_net_dev(&content) {
+            if interface == "lo" && !self.include_loopback {
+                continue;
+            }
+            let labels = [self.nodename.as_str(), &sanitize_label_value(interface)];
+            self.rx_total.with_label_values(&labels).set(rx);
+            self.tx_total.with_label_values(&labels).set(tx);
+            // Counters restart when an interface is recreated
+            let last = self.last.get(interface).filter(|(last_rx, last_tx)| rx >= *last_rx && tx >= *last_tx);
+            if let Some(&(last_rx, last_tx)) = last {
+                self.rx_rate.with_label_values(&labels).set((rx - last_rx) / elapsed);
+                self.tx_rate.with_label_values(&labels).set((tx - last_tx) / elapsed);
+            }
+            current.insert(interface.to_string(), (rx, tx));
+        }
+        self.last = current;
+        Ok(())
+    }
+}
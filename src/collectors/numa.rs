@@ -0,0 +1,83 @@
+use super::{entries_with_prefix, Collector};
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const NODE_DIR: &str = "/sys/devices/system/node";
+
+/// Memory usage of every NUMA node
+pub struct NumaMemCollector {
+    nodename: String,
+    total: GaugeVec,
+    free: GaugeVec,
+    used: GaugeVec,
+}
+
+impl NumaMemCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let labels = &["nodename", "numa_node"];
+        let total = GaugeVec::new(
+            Opts::new("amd_numa_node_mem_total_bytes", "NUMA node total memory"),
+            labels
+        ).unwrap();
+        let free = GaugeVec::new(
+            Opts::new("amd_numa_node_mem_free_bytes", "NUMA node free memory"),
+            labels
+        ).unwrap();
+        let used = GaugeVec::new(
+            Opts::new("amd_numa_node_mem_used_bytes", "NUMA node used memory"),
+            labels
+        ).unwrap();
+
+        registry.register(&total);
+        registry.register(&free);
+        registry.register(&used);
+
+        Self {
+            nodename: nodename.to_string(),
+            total,
+            free,
+            used,
+        }
+    }
+}
+
+/// Parses `Node 0 MemTotal:  16384 kB` lines into byte values
+fn read_node_meminfo(path: &Path) -> std::io::Result<HashMap<String, f64>> {
+    let content = fs::read_to_string(path)?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(2);
+            let key = fields.next()?.trim_end_matches(':');
+            let value: f64 = fields.next()?.parse().ok()?;
+            let scale = if fields.next() == Some("kB") { 1024.0 } else { 1.0 };
+            Some((key.to_string(), value * scale))
+        })
+        .collect())
+}
+
+impl Collector for NumaMemCollector {
+    fn name(&self) -> &'static str {
+        "numa"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        for (name, path) in entries_with_prefix(NODE_DIR, "node") {
+            let Some(node) = name.strip_prefix("node").filter(|n| n.parse::<u32>().is_ok()) else {
+                continue;
+            };
+            let meminfo = read_node_meminfo(&path.join("meminfo"))?;
+            let (Some(total), Some(free)) = (meminfo.get("MemTotal"), meminfo.get("MemFree")) else {
+                continue;
+            };
+            let labels = [self.nodename.as_str(), node];
+            self.total.with_label_values(&labels).set(*total);
+            self.free.with_label_values(&labels).set(*free);
+            self.used.with_label_values(&labels).set(total - free);
+        }
+        Ok(())
+    }
+}
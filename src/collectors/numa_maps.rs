@@ -0,0 +1,87 @@
+use super::{entries_with_prefix, read_trimmed, Collector};
+use crate::label::sanitize_label_value;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use std::fs;
+
+/// Splits the pages of a traced process into those on the NUMA node it is
+/// currently running on and those on other nodes, from `/proc/<pid>/numa_maps`
+pub struct NumaMapsCollector {
+    nodename: String,
+    pid: u32,
+    local: GaugeVec,
+    remote: GaugeVec,
+}
+
+impl NumaMapsCollector {
+    pub fn new(registry: &Registrar, nodename: &str, pid: u32) -> Self {
+        let labels = &["nodename", "pid", "comm"];
+        let local = GaugeVec::new(
+            Opts::new("amd_process_numa_local_pages", "Pages of the traced process on its current NUMA node"),
+            labels
+        ).unwrap();
+        let remote = GaugeVec::new(
+            Opts::new("amd_process_numa_remote_pages", "Pages of the traced process on other NUMA nodes"),
+            labels
+        ).unwrap();
+
+        registry.register(&local);
+        registry.register(&remote);
+
+        Self {
+            nodename: nodename.to_string(),
+            pid,
+            local,
+            remote,
+        }
+    }
+
+    /// NUMA node of the CPU the process last ran on
+    fn current_node(&self) -> Option<u32> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", self.pid)).ok()?;
+        // Fields after the parenthesized comm start at field 3
+        let cpu = stat.rsplit_once(')')?.1.split_whitespace().nth(36)?;
+        entries_with_prefix(format!("/sys/devices/system/cpu/cpu{}", cpu), "node")
+            .into_iter()
+            .find_map(|(name, _)| name.strip_prefix("node")?.parse().ok())
+    }
+}
+
+impl Collector for NumaMapsCollector {
+    fn name(&self) -> &'static str {
+        "numa_maps"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        self.local.reset();
+        self.remote.reset();
+
+        let numa_maps = fs::read_to_string(format!("/proc/{}/numa_maps", self.pid))?;
+        let comm = read_trimmed(format!("/proc/{}/comm", self.pid)).unwrap_or_default();
+        let current = self.current_node();
+
+        let mut local = 0.0;
+        let mut remote = 0.0;
+        for field in numa_maps.split_whitespace() {
+            // Per-node page counts look like `N1=42`
+            let Some((node, pages)) = field.strip_prefix('N').and_then(|f| f.split_once('=')) else {
+                continue;
+            };
+            let (Ok(node), Ok(pages)) = (node.parse::<u32>(), pages.parse::<f64>()) else {
+                continue;
+            };
+            if Some(node) == current {
+                local += pages;
+            } else {
+                remote += pages;
+            }
+        }
+
+        let pid = self.pid.to_string();
+        let comm = sanitize_label_value(&comm);
+        let labels = [self.nodename.as_str(), pid.as_str(), comm.as_str()];
+        self.local.with_label_values(&labels).set(local);
+        self.remote.with_label_values(&labels).set(remote);
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use super::Collector;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use std::io::ErrorKind;
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+const KMSG: &str = "/dev/kmsg";
+/// Every read of /dev/kmsg returns one record of at most this size, so no
+/// more than this is ever buffered (the kernel's CONSOLE_EXT_LOG_MAX)
+const RECORD_MAX: usize = 8192;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counts OOM killer invocations in the kernel log
+pub struct OomCollector {
+    nodename: String,
+    kills: GaugeVec,
+    count: Arc<AtomicU64>,
+}
+
+impl OomCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let kills = GaugeVec::new(
+            Opts::new("amd_oom_kills_total", "Processes killed by the OOM killer, system-wide or in a cgroup"),
+            &["nodename"]
+        ).unwrap();
+        registry.register(&kills);
+
+        let count = Arc::new(AtomicU64::new(0));
+        tokio::spawn(follow_kmsg(Arc::clone(&count)));
+
+        Self {
+            nodename: nodename.to_string(),
+            kills,
+            count,
+        }
+    }
+}
+
+/// Reads the kernel log from the oldest record still buffered and counts
+/// the OOM kill messages, both "Out of memory: Kill..." and "Memory cgroup
+/// out of memory: Kill..."
+async fn follow_kmsg(count: Arc<AtomicU64>) {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(KMSG);
+    let mut file = match file {
+        Ok(file) => tokio::fs::File::from_std(file),
+        Err(e) => {
+            eprintln!("Warning: {} is not available, OOM kills will not be counted: {}", KMSG, e);
+            return;
+        }
+    };
+    let mut record = vec![0u8; RECORD_MAX];
+    loop {
+        match file.read(&mut record).await {
+            Ok(0) => return,
+            Ok(len) => {
+                // `<prefix>;<message>`, continuation lines follow the message
+                let text = String::from_utf8_lossy(&record[..len]);
+                let message = text.split_once(';').map_or(&*text, |(_, message)| message);
+                if message.contains("ut of memory: Kill") {
+                    count.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => tokio::time::sleep(POLL_INTERVAL).await,
+            // Records were overwritten before they were read
+            Err(e) if e.raw_os_error() == Some(libc::EPIPE) => {}
+            Err(e) => {
+                eprintln!("Failed to read {}, OOM kills will no longer be counted: {}", KMSG, e);
+                return;
+            }
+        }
+    }
+}
+
+impl Collector for OomCollector {
+    fn name(&self) -> &'static str {
+        "oom"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        let kills = self.count.load(Ordering::Relaxed);
+        self.kills.with_label_values(&[&self.nodename]).set(kills as f64);
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use super::df::{default_bytes_per_beat, DfBandwidthCollector, DfEventConfig, DfTraffic};
+use crate::registration::Registrar;
+use serde::Deserialize;
+
+/// `[[pcie]]` entry of the config file: the DF events counting the DMA
+/// data beats of the I/O master behind one PCIe root complex
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PcieConfig {
+    /// Root bus number, e.g. `0000:40`
+    pub bus: String,
+    #[serde(default)]
+    pub slot: String,
+    pub read: DfEventConfig,
+    pub write: DfEventConfig,
+    #[serde(default = "default_bytes_per_beat")]
+    pub bytes_per_beat: f64,
+}
+
+/// PCIe DMA bandwidth counted by the `amd_df` perf PMU
+pub fn collector(registry: &Registrar, nodename: &str, links: &[PcieConfig]) -> std::io::Result<DfBandwidthCollector> {
+    let traffic = links
+        .iter()
+        .map(|link| DfTraffic {
+            labels: vec![link.bus.clone(), link.slot.clone()],
+            read: link.read,
+            write: link.write,
+            bytes_per_beat: link.bytes_per_beat,
+        })
+        .collect();
+    DfBandwidthCollector::new(
+        "pcie",
+        registry,
+        nodename,
+        ("amd_pcie_read_gbps", "PCIe DMA read bandwidth (GB/s)"),
+        ("amd_pcie_write_gbps", "PCIe DMA write bandwidth (GB/s)"),
+        &["bus", "slot"],
+        traffic,
+    )
+}
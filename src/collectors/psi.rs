@@ -0,0 +1,91 @@
+use super::Collector;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use std::collections::HashMap;
+use std::fs;
+
+const PRESSURE_DIR: &str = "/proc/pressure";
+const RESOURCES: [&str; 3] = ["cpu", "memory", "io"];
+const KINDS: [&str; 2] = ["some", "full"];
+/// Fields of a PSI line and the help of their gauges
+const FIELDS: [(&str, &str); 4] = [
+    ("avg10", "Percent of the last 10 seconds"),
+    ("avg60", "Percent of the last 60 seconds"),
+    ("avg300", "Percent of the last 300 seconds"),
+    ("total", "Total time"),
+];
+
+/// Pressure stall information: the time tasks waited for CPU, memory or I/O
+pub struct PsiCollector {
+    nodename: String,
+    /// Keyed by (resource, kind, field)
+    gauges: HashMap<(&'static str, &'static str, &'static str), GaugeVec>,
+}
+
+impl PsiCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let mut gauges = HashMap::new();
+        for resource in RESOURCES {
+            for kind in KINDS {
+                let waiting = match kind {
+                    "some" => "some tasks",
+                    _ => "all non-idle tasks",
+                };
+                for (field, help) in FIELDS {
+                    let (name, help) = match field {
+                        "total" => (
+                            format!("amd_psi_{}_{}_total_usec", resource, kind),
+                            format!("{} {} were stalled on {}, in microseconds", help, waiting, resource),
+                        ),
+                        _ => (
+                            format!("amd_psi_{}_{}_{}", resource, kind, field),
+                            format!("{} in which {} were stalled on {}", help, waiting, resource),
+                        ),
+                    };
+                    let gauge = GaugeVec::new(Opts::new(name, help), &["nodename"]).unwrap();
+                    registry.register(&gauge);
+                    gauges.insert((resource, kind, field), gauge);
+                }
+            }
+        }
+
+        Self {
+            nodename: nodename.to_string(),
+            gauges,
+        }
+    }
+}
+
+impl Collector for PsiCollector {
+    fn name(&self) -> &'static str {
+        "psi"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        for resource in RESOURCES {
+            // Missing without CONFIG_PSI or with psi=0
+            let Ok(content) = fs::read_to_string(format!("{}/{}", PRESSURE_DIR, resource)) else {
+                continue;
+            };
+            // some avg10=0.00 avg60=0.00 avg300=0.00 total=0
+            for line in content.lines() {
+                let mut fields = line.split_whitespace();
+                let Some(kind) = fields.next().and_then(|kind| KINDS.into_iter().find(|k| *k == kind)) else {
+                    continue;
+                };
+                for field in fields {
+                    let Some((key, value)) = field.split_once('=') else {
+                        continue;
+                    };
+                    let Some((field, _)) = FIELDS.into_iter().find(|(name, _)| *name == key) else {
+                        continue;
+                    };
+                    if let (Some(gauge), Ok(value)) = (self.gauges.get(&(resource, kind, field)), value.parse()) {
+                        gauge.with_label_values(&[&self.nodename]).set(value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
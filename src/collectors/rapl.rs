@@ -0,0 +1,86 @@
+use super::{entries_with_prefix, read_f64, read_trimmed, Collector};
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Named after Intel, but the `intel_rapl` driver serves AMD energy counters too
+const POWERCAP_DIR: &str = "/sys/class/powercap";
+
+/// Average socket and DRAM power over the collection interval from the
+/// RAPL energy counters
+pub struct RaplCollector {
+    nodename: String,
+    socket_power: GaugeVec,
+    dram_power: GaugeVec,
+    /// Energy in µJ and time of the previous read, keyed by zone directory
+    last: HashMap<PathBuf, (f64, Instant)>,
+}
+
+impl RaplCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let socket_power = GaugeVec::new(
+            Opts::new("amd_rapl_socket_power_watts", "Average package power over the collection interval"),
+            &["nodename", "socket"]
+        ).unwrap();
+        let dram_power = GaugeVec::new(
+            Opts::new("amd_rapl_dram_power_watts", "Average DRAM power over the collection interval"),
+            &["nodename", "socket"]
+        ).unwrap();
+        registry.register(&socket_power);
+        registry.register(&dram_power);
+
+        Self {
+            nodename: nodename.to_string(),
+            socket_power,
+            dram_power,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Watts since the previous call for the same zone, None on the first
+    fn power(&mut self, zone: &Path) -> Option<f64> {
+        let energy = read_f64(zone.join("energy_uj"))?;
+        let now = Instant::now();
+        let (last_energy, last_time) = self.last.insert(zone.to_path_buf(), (energy, now))?;
+        let mut delta = energy - last_energy;
+        // The counter wraps at max_energy_range_uj
+        if delta < 0.0 {
+            delta += read_f64(zone.join("max_energy_range_uj"))?;
+        }
+        Some(delta / 1e6 / now.duration_since(last_time).as_secs_f64())
+    }
+}
+
+impl Collector for RaplCollector {
+    fn name(&self) -> &'static str {
+        "rapl"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        // Package zones are intel-rapl:<n>, their subzones intel-rapl:<n>:<m>
+        for (name, package) in entries_with_prefix(POWERCAP_DIR, "intel-rapl:") {
+            if name.matches(':').count() != 1 {
+                continue;
+            }
+            let Some(socket) = read_trimmed(package.join("name"))
+                .and_then(|zone| zone.strip_prefix("package-").map(str::to_string))
+            else {
+                continue;
+            };
+            if let Some(watts) = self.power(&package) {
+                self.socket_power.with_label_values(&[&self.nodename, &socket]).set(watts);
+            }
+            let dram = entries_with_prefix(&package, &format!("{}:", name))
+                .into_iter()
+                .find(|(_, zone)| read_trimmed(zone.join("name")).as_deref() == Some("dram"));
+            if let Some((_, dram)) = dram {
+                if let Some(watts) = self.power(&dram) {
+                    self.dram_power.with_label_values(&[&self.nodename, &socket]).set(watts);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use super::{read_key_values, read_trimmed, Collector};
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+
+const VMSTAT: &str = "/proc/vmstat";
+const LOADAVG: &str = "/proc/loadavg";
+const LOAD_WINDOWS: [&str; 3] = ["1m", "5m", "15m"];
+
+/// Migrations and run queue length, both of which cost cache locality
+pub struct SchedCollector {
+    nodename: String,
+    migrations: GaugeVec,
+    run_queue: GaugeVec,
+}
+
+impl SchedCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let migrations = GaugeVec::new(
+            Opts::new("amd_cpu_migrations_total", "Pages the kernel migrated for NUMA balancing or compaction (pgmigrate_success)"),
+            &["nodename"]
+        ).unwrap();
+        let run_queue = GaugeVec::new(
+            Opts::new("amd_run_queue_length", "Load average per online CPU"),
+            &["nodename", "window"]
+        ).unwrap();
+        registry.register(&migrations);
+        registry.register(&run_queue);
+
+        Self {
+            nodename: nodename.to_string(),
+            migrations,
+            run_queue,
+        }
+    }
+}
+
+impl Collector for SchedCollector {
+    fn name(&self) -> &'static str {
+        "sched"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        if let Some(migrations) = read_key_values(VMSTAT)?.get("pgmigrate_success") {
+            self.migrations.with_label_values(&[&self.nodename]).set(*migrations);
+        }
+        let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) }.max(1) as f64;
+        let loadavg = read_trimmed(LOADAVG).unwrap_or_default();
+        for (window, load) in LOAD_WINDOWS.iter().zip(loadavg.split_whitespace()) {
+            if let Ok(load) = load.parse::<f64>() {
+                self.run_queue
+                    .with_label_values(&[&self.nodename, window])
+                    .set(load / cpus);
+            }
+        }
+        Ok(())
+    }
+}
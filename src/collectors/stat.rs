@@ -0,0 +1,119 @@
+use super::{entries_with_prefix, read_key_values, Collector};
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use std::fs;
+use std::time::Instant;
+
+const STAT: &str = "/proc/stat";
+/// Columns of the `softirq` line after the total, in the order of the
+/// kernel's `softirq_to_name`
+const SOFTIRQS: [&str; 10] = [
+    "HI", "TIMER", "NET_TX", "NET_RX", "BLOCK", "IRQ_POLL", "TASKLET", "SCHED", "HRTIMER", "RCU",
+];
+
+/// Kernel activity that runs on the same cores as the profiled workload
+pub struct StatCollector {
+    nodename: String,
+    softirqs: GaugeVec,
+    context_switches: GaugeVec,
+    context_switch_rate: GaugeVec,
+    /// Context switches and time of the previous read
+    last_switches: Option<(f64, Instant)>,
+    /// Set with --process-syscalls
+    syscalls: Option<GaugeVec>,
+}
+
+impl StatCollector {
+    pub fn new(registry: &Registrar, nodename: &str, process_syscalls: bool) -> Self {
+        let softirqs = GaugeVec::new(
+            Opts::new("amd_softirq_total", "Softirqs handled since boot"),
+            &["nodename", "type"]
+        ).unwrap();
+        let context_switches = GaugeVec::new(
+            Opts::new("amd_ctxt_switches_total", "Context switches of all CPUs since boot"),
+            &["nodename"]
+        ).unwrap();
+        let context_switch_rate = GaugeVec::new(
+            Opts::new("amd_ctxt_switches_per_second", "Context switches of all CPUs since the previous collection, per second"),
+            &["nodename"]
+        ).unwrap();
+        registry.register(&softirqs);
+        registry.register(&context_switches);
+        registry.register(&context_switch_rate);
+
+        let syscalls = process_syscalls.then(|| {
+            let syscalls = GaugeVec::new(
+                Opts::new(
+                    "amd_system_calls_total",
+                    "Read and write system calls of the running processes; drops when a process exits",
+                ),
+                &["nodename", "type"]
+            ).unwrap();
+            registry.register(&syscalls);
+            syscalls
+        });
+
+        Self {
+            nodename: nodename.to_string(),
+            softirqs,
+            context_switches,
+            context_switch_rate,
+            last_switches: None,
+            syscalls,
+        }
+    }
+}
+
+/// Sums `syscr` and `syscw` over `/proc/<pid>/io`; the kernel keeps no
+/// system-wide count of system calls
+fn process_syscalls() -> (f64, f64) {
+    let (mut read, mut write) = (0.0, 0.0);
+    for (name, path) in entries_with_prefix("/proc", "") {
+        if name.parse::<u32>().is_err() {
+            continue;
+        }
+        // Processes exit between listing /proc and reading their io
+        let Ok(io) = read_key_values(path.join("io")) else {
+            continue;
+        };
+        read += io.get("syscr").unwrap_or(&0.0);
+        write += io.get("syscw").unwrap_or(&0.0);
+    }
+    (read, write)
+}
+
+impl Collector for StatCollector {
+    fn name(&self) -> &'static str {
+        "stat"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        let stat = fs::read_to_string(STAT)?;
+        if let Some(line) = stat.lines().find_map(|line| line.strip_prefix("softirq ")) {
+            // The first column is the total
+            for (kind, count) in SOFTIRQS.iter().zip(line.split_whitespace().skip(1)) {
+                if let Ok(count) = count.parse() {
+                    self.softirqs.with_label_values(&[&self.nodename, kind]).set(count);
+                }
+            }
+        }
+
+        let switches = stat.lines().find_map(|line| line.strip_prefix("ctxt ")?.trim().parse::<f64>().ok());
+        if let Some(switches) = switches {
+            let now = Instant::now();
+            self.context_switches.with_label_values(&[&self.nodename]).set(switches);
+            if let Some((last, last_read)) = self.last_switches {
+                let rate = (switches - last) / now.duration_since(last_read).as_secs_f64();
+                self.context_switch_rate.with_label_values(&[&self.nodename]).set(rate);
+            }
+            self.last_switches = Some((switches, now));
+        }
+
+        if let Some(syscalls) = &self.syscalls {
+            let (read, write) = process_syscalls();
+            syscalls.with_label_values(&[&self.nodename, "read"]).set(read);
+            syscalls.with_label_values(&[&self.nodename, "write"]).set(write);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use super::{entries_with_prefix, read_f64, read_trimmed, Collector};
+use crate::label::sanitize_label_value;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+
+const THERMAL_DIR: &str = "/sys/class/thermal";
+const THROTTLING_CELSIUS: f64 = 90.0;
+
+/// Temperatures of the kernel thermal zones (k10temp and friends)
+pub struct ThermalCollector {
+    nodename: String,
+    temperature: GaugeVec,
+    throttling: GaugeVec,
+}
+
+impl ThermalCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let temperature = GaugeVec::new(
+            Opts::new("amd_cpu_temperature_celsius", "Thermal zone temperature"),
+            &["nodename", "zone", "type"]
+        ).unwrap();
+        let throttling = GaugeVec::new(
+            Opts::new("amd_cpu_thermal_throttling", "1 if any thermal zone is above 90 °C"),
+            &["nodename"]
+        ).unwrap();
+
+        registry.register(&temperature);
+        registry.register(&throttling);
+
+        Self {
+            nodename: nodename.to_string(),
+            temperature,
+            throttling,
+        }
+    }
+}
+
+impl Collector for ThermalCollector {
+    fn name(&self) -> &'static str {
+        "thermal"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        let zones = entries_with_prefix(THERMAL_DIR, "thermal_zone");
+        if zones.is_empty() {
+            return Ok(());
+        }
+
+        let mut hottest = f64::MIN;
+        for (zone, path) in zones {
+            let Some(millidegrees) = read_f64(path.join("temp")) else {
+                continue;
+            };
+            let celsius = millidegrees / 1000.0;
+            let zone = sanitize_label_value(&zone);
+            let kind = sanitize_label_value(&read_trimmed(path.join("type")).unwrap_or_default());
+            self.temperature
+                .with_label_values(&[&self.nodename, &zone, &kind])
+                .set(celsius);
+            hottest = hottest.max(celsius);
+        }
+
+        let throttling = if hottest > THROTTLING_CELSIUS { 1.0 } else { 0.0 };
+        self.throttling.with_label_values(&[&self.nodename]).set(throttling);
+        Ok(())
+    }
+}
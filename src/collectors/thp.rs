@@ -0,0 +1,80 @@
+use super::{read_key_values, read_trimmed, Collector};
+use crate::label::sanitize_label_value;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+
+const VMSTAT: &str = "/proc/vmstat";
+const THP_ENABLED: &str = "/sys/kernel/mm/transparent_hugepage/enabled";
+
+const VMSTAT_FIELDS: &[(&str, &str)] = &[
+    ("thp_fault_alloc", "Huge pages allocated on page fault"),
+    ("thp_collapse_alloc", "Huge pages allocated by khugepaged collapsing"),
+    ("thp_split_page", "Huge pages split into regular pages"),
+];
+
+/// Transparent huge page activity and the configured THP mode
+pub struct ThpCollector {
+    nodename: String,
+    counters: Vec<(&'static str, GaugeVec)>,
+    mode: GaugeVec,
+}
+
+impl ThpCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let counters: Vec<(&'static str, GaugeVec)> = VMSTAT_FIELDS
+            .iter()
+            .map(|&(field, help)| {
+                let gauge = GaugeVec::new(
+                    Opts::new(format!("amd_{}", field), help),
+                    &["nodename"]
+                ).unwrap();
+                registry.register(&gauge);
+                (field, gauge)
+            })
+            .collect();
+
+        let mode = GaugeVec::new(
+            Opts::new("amd_thp_mode_info", "Transparent huge page mode"),
+            &["nodename", "thp_mode"]
+        ).unwrap();
+        registry.register(&mode);
+
+        Self {
+            nodename: nodename.to_string(),
+            counters,
+            mode,
+        }
+    }
+}
+
+/// The bracketed entry of a sysfs choice list such as `always [madvise] never`
+fn selected_mode(choices: &str) -> Option<&str> {
+    choices
+        .split_whitespace()
+        .find_map(|choice| choice.strip_prefix('[')?.strip_suffix(']'))
+}
+
+impl Collector for ThpCollector {
+    fn name(&self) -> &'static str {
+        "thp"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        let vmstat = read_key_values(VMSTAT)?;
+        for (field, gauge) in &self.counters {
+            if let Some(value) = vmstat.get(*field) {
+                gauge.with_label_values(&[&self.nodename]).set(*value);
+            }
+        }
+
+        if let Some(choices) = read_trimmed(THP_ENABLED) {
+            if let Some(mode) = selected_mode(&choices) {
+                self.mode.reset();
+                self.mode
+                    .with_label_values(&[&self.nodename, &sanitize_label_value(mode)])
+                    .set(1.0);
+            }
+        }
+        Ok(())
+    }
+}
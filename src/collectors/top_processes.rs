@@ -0,0 +1,93 @@
+use super::{entries_with_prefix, Collector};
+use crate::label::sanitize_label_value;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use std::collections::HashMap;
+use std::fs;
+use std::time::Instant;
+
+/// The processes that used the most CPU time since the previous collection,
+/// to tell who is behind a change in the system-wide counters
+pub struct TopProcessesCollector {
+    nodename: String,
+    count: usize,
+    ticks_per_second: f64,
+    cpu: GaugeVec,
+    /// utime + stime in clock ticks of every process at the previous read
+    last: HashMap<u32, f64>,
+    last_read: Instant,
+}
+
+/// (comm, utime + stime) from `/proc/<pid>/stat`
+fn cpu_ticks(stat: &str) -> Option<(&str, f64)> {
+    // comm may contain spaces and parentheses, it ends at the last ')'
+    let (head, tail) = stat.rsplit_once(')')?;
+    let (_, comm) = head.split_once('(')?;
+    let mut fields = tail.split_whitespace().skip(11);
+    let utime: f64 = fields.next()?.parse().ok()?;
+    let stime: f64 = fields.next()?.parse().ok()?;
+    Some((comm, utime + stime))
+}
+
+impl TopProcessesCollector {
+    pub fn new(registry: &Registrar, nodename: &str, count: usize) -> Self {
+        let cpu = GaugeVec::new(
+            Opts::new(
+                "amd_top_process_cpu_pct",
+                "CPU usage since the previous collection of the busiest processes, 100 per core",
+            ),
+            &["nodename", "pid", "comm"]
+        ).unwrap();
+        registry.register(&cpu);
+
+        Self {
+            nodename: nodename.to_string(),
+            count,
+            ticks_per_second: unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64,
+            cpu,
+            last: HashMap::new(),
+            last_read: Instant::now(),
+        }
+    }
+}
+
+impl Collector for TopProcessesCollector {
+    fn name(&self) -> &'static str {
+        "top_processes"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        let elapsed = self.last_read.elapsed().as_secs_f64();
+        self.last_read = Instant::now();
+
+        let mut current = HashMap::new();
+        let mut usage = Vec::new();
+        for (name, path) in entries_with_prefix("/proc", "") {
+            let Ok(pid) = name.parse::<u32>() else {
+                continue;
+            };
+            // Processes exit between listing /proc and reading their stat
+            let Ok(stat) = fs::read_to_string(path.join("stat")) else {
+                continue;
+            };
+            let Some((comm, ticks)) = cpu_ticks(&stat) else {
+                continue;
+            };
+            current.insert(pid, ticks);
+            if let Some(last) = self.last.get(&pid) {
+                let percent = (ticks - last) / self.ticks_per_second / elapsed * 100.0;
+                usage.push((pid, sanitize_label_value(comm), percent));
+            }
+        }
+        self.last = current;
+
+        usage.sort_by(|a, b| b.2.total_cmp(&a.2));
+        self.cpu.reset();
+        for (pid, comm, percent) in usage.into_iter().take(self.count) {
+            self.cpu
+                .with_label_values(&[&self.nodename, &pid.to_string(), &comm])
+                .set(percent);
+        }
+        Ok(())
+    }
+}
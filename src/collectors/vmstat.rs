@@ -0,0 +1,57 @@
+use super::{read_key_values, Collector};
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+
+const VMSTAT: &str = "/proc/vmstat";
+
+/// Page faults and swapping, which evict cached data behind the counters' back
+pub struct VmstatCollector {
+    nodename: String,
+    major_faults: GaugeVec,
+    minor_faults: GaugeVec,
+    swap_in: GaugeVec,
+    swap_out: GaugeVec,
+}
+
+impl VmstatCollector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let gauge = |name: &str, help: &str| {
+            let gauge = GaugeVec::new(Opts::new(name, help), &["nodename"]).unwrap();
+            registry.register(&gauge);
+            gauge
+        };
+        Self {
+            nodename: nodename.to_string(),
+            major_faults: gauge("amd_page_faults_major_total", "Page faults that had to read from disk"),
+            minor_faults: gauge("amd_page_faults_minor_total", "Page faults served without disk I/O"),
+            swap_in: gauge("amd_swap_pages_in_total", "Pages read from swap"),
+            swap_out: gauge("amd_swap_pages_out_total", "Pages written to swap"),
+        }
+    }
+}
+
+impl Collector for VmstatCollector {
+    fn name(&self) -> &'static str {
+        "vmstat"
+    }
+
+    fn collect(&mut self) -> std::io::Result<()> {
+        let vmstat = read_key_values(VMSTAT)?;
+        let labels = [self.nodename.as_str()];
+        let major = vmstat.get("pgmajfault");
+        if let Some(major) = major {
+            self.major_faults.with_label_values(&labels).set(*major);
+        }
+        // pgfault counts major faults as well
+        if let Some(all) = vmstat.get("pgfault") {
+            self.minor_faults.with_label_values(&labels).set(all - major.unwrap_or(&0.0));
+        }
+        if let Some(swap_in) = vmstat.get("pswpin") {
+            self.swap_in.with_label_values(&labels).set(*swap_in);
+        }
+        if let Some(swap_out) = vmstat.get("pswpout") {
+            self.swap_out.with_label_values(&labels).set(*swap_out);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+//! Positions of the values in a system-wide AMDuProfPcm report row.
+//!
+//! The whole layout is listed even where no code refers to a column by name.
+
+#![allow(dead_code)]
+
+pub const IC_FETCH_MISS_RATIO: usize = 0;
+pub const OP_CACHE_FETCH_MISS_RATIO: usize = 1;
+pub const IC_ACCESS_PTI: usize = 2;
+pub const IC_MISS_PTI: usize = 3;
+pub const DC_ACCESS_PTI: usize = 4;
+pub const L2_ACCESS_PTI: usize = 5;
+pub const L2_ACCESS_FROM_IC_MISS_PTI: usize = 6;
+pub const L2_ACCESS_FROM_DC_MISS_PTI: usize = 7;
+pub const L2_ACCESS_FROM_L2_HWPF_PTI: usize = 8;
+pub const L2_MISS_PTI: usize = 9;
+pub const L2_MISS_FROM_IC_MISS_PTI: usize = 10;
+pub const L2_MISS_FROM_DC_MISS_PTI: usize = 11;
+pub const L2_MISS_FROM_L2_HWPF_PTI: usize = 12;
+pub const L2_HIT_PTI: usize = 13;
+pub const L2_HIT_FROM_IC_MISS_PTI: usize = 14;
+pub const L2_HIT_FROM_DC_MISS_PTI: usize = 15;
+pub const L2_HIT_FROM_L2_HWPF_PTI: usize = 16;
+pub const L3_ACCESS: usize = 17;
+pub const L3_MISS: usize = 18;
+pub const L3_MISS_PERCENT: usize = 19;
+pub const L3_HIT_PERCENT: usize = 20;
+pub const AVE_L3_MISS_LATENCY_NS: usize = 21;
+pub const TOTAL_MEM_BW_GBPS: usize = 22;
+pub const LOCAL_DRAM_READ_DATA_BYTES_GBPS: usize = 23;
+pub const LOCAL_DRAM_WRITE_DATA_BYTES_GBPS: usize = 24;
+pub const REMOTE_DRAM_READ_DATA_BYTES_GBPS: usize = 25;
+pub const REMOTE_DRAM_WRITE_DATA_BYTES_GBPS: usize = 26;
+pub const TOTAL_MEM_RDBW_GBPS: usize = 27;
+pub const TOTAL_MEM_WRBW_GBPS: usize = 28;
+
+pub const COUNT: usize = 29;
+
+/// Whether a column is a rate that adds up across sockets; the rest are
+/// ratios and latencies that are averaged
+pub fn is_additive(column: usize) -> bool {
+    matches!(column, L3_ACCESS | L3_MISS) || (TOTAL_MEM_BW_GBPS..COUNT).contains(&column)
+}
+
+/// Combines rows collected for disjoint sets of sockets into one system row
+pub fn merge(rows: &[Vec<f64>]) -> Vec<f64> {
+    (0..COUNT)
+        .map(|column| {
+            let values: Vec<f64> = rows.iter().map(|row| row[column]).filter(|value| !value.is_nan()).collect();
+            if values.is_empty() {
+                return f64::NAN;
+            }
+            let sum: f64 = values.iter().sum();
+            if is_additive(column) {
+                sum
+            } else {
+                sum / values.len() as f64
+            }
+        })
+        .collect()
+}
+
+/// Physically possible range of a column; anything outside comes from
+/// counter wraparound or driver bugs
+pub fn bounds(column: usize) -> (f64, f64) {
+    match column {
+        IC_FETCH_MISS_RATIO | OP_CACHE_FETCH_MISS_RATIO => (0.0, 1.0),
+        L3_MISS_PERCENT | L3_HIT_PERCENT => (0.0, 100.0),
+        _ => (0.0, f64::INFINITY),
+    }
+}
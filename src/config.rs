@@ -0,0 +1,44 @@
+use crate::alerts::{self, AlertRule};
+use crate::collectors::if_link::IfLinkConfig;
+use crate::collectors::pcie::PcieConfig;
+use crate::health_score::HealthScoreConfig;
+use crate::transform::{self, TransformConfig};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Contents of the file passed with `--config`
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Replacement help strings keyed by metric name
+    pub help_overrides: HashMap<String, String>,
+    /// Additional names exported for a metric, keyed by its uProf-derived name
+    pub aliases: HashMap<String, String>,
+    pub alerts: AlertsConfig,
+    pub health_score: HealthScoreConfig,
+    /// Data Fabric events of the Infinity Fabric links to export
+    pub if_links: Vec<IfLinkConfig>,
+    /// Data Fabric events of the PCIe root complexes to export
+    pub pcie: Vec<PcieConfig>,
+    /// Stages applied in order to every collected row
+    pub transforms: Vec<TransformConfig>,
+}
+
+/// `[alerts]` section: threshold rules notified through webhooks
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    pub rules: Vec<AlertRule>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        let config: Self = toml::from_str(&content)?;
+        transform::validate(&config.transforms)?;
+        alerts::validate(&config.alerts.rules)?;
+        Ok(config)
+    }
+}
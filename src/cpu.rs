@@ -0,0 +1,84 @@
+use crate::columns;
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Value of a field of the first processor entry in `/proc/cpuinfo`
+pub fn cpuinfo_field(name: &str) -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == name).then(|| value.trim().to_string())
+    })
+}
+
+pub fn is_intel() -> bool {
+    cpuinfo_field("vendor_id").as_deref() == Some("GenuineIntel")
+}
+
+/// Zen generations supported by AMD uProf 5.1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ZenGeneration {
+    Zen2,
+    Zen3,
+    Zen4,
+    Zen5,
+}
+
+impl ZenGeneration {
+    pub fn detect() -> Option<Self> {
+        if cpuinfo_field("vendor_id")? != "AuthenticAMD" {
+            return None;
+        }
+        let family: u32 = cpuinfo_field("cpu family")?.parse().ok()?;
+        let model: u32 = cpuinfo_field("model")?.parse().ok()?;
+
+        match (family, model) {
+            (0x17, 0x30..=0x3F | 0x47 | 0x60..=0x7F | 0x84..=0x87 | 0x90..=0xAF) => Some(Self::Zen2),
+            (0x19, 0x10..=0x1F | 0x60..=0x7F | 0xA0..=0xAF) => Some(Self::Zen4),
+            (0x19, _) => Some(Self::Zen3),
+            (0x1A, _) => Some(Self::Zen5),
+            // Zen 1 and anything newer is left to the generation-independent columns
+            _ => None,
+        }
+    }
+
+    /// Whether AMDuProfPcm reports the given column on this generation
+    pub fn supports(self, column: usize) -> bool {
+        !is_generation_specific(column) || matches!(self, Self::Zen4 | Self::Zen5)
+    }
+}
+
+/// Whether AMDuProfPcm reports a column only on some generations
+pub fn is_generation_specific(column: usize) -> bool {
+    matches!(
+        column,
+        columns::L2_ACCESS_FROM_L2_HWPF_PTI | columns::L2_MISS_FROM_L2_HWPF_PTI | columns::L2_HIT_FROM_L2_HWPF_PTI
+    )
+}
+
+/// Whether a column is exported; without a detected generation only the
+/// columns every generation reports are
+pub fn exports(generation: Option<ZenGeneration>, column: usize) -> bool {
+    match generation {
+        Some(generation) => generation.supports(column),
+        None => !is_generation_specific(column),
+    }
+}
+
+/// Online CPUs of every socket, keyed by physical package id
+pub fn package_cpus() -> BTreeMap<u32, Vec<usize>> {
+    let mut packages: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    let cpus = fs::read_dir("/sys/devices/system/cpu").into_iter().flatten().filter_map(|entry| {
+        entry.ok()?.file_name().to_str()?.strip_prefix("cpu")?.parse::<usize>().ok()
+    });
+    for cpu in cpus {
+        let path = format!("/sys/devices/system/cpu/cpu{}/topology/physical_package_id", cpu);
+        if let Some(package) = fs::read_to_string(path).ok().and_then(|id| id.trim().parse().ok()) {
+            packages.entry(package).or_default().push(cpu);
+        }
+    }
+    for cpus in packages.values_mut() {
+        cpus.sort_unstable();
+    }
+    packages
+}
@@ -0,0 +1,168 @@
+use std::fs;
+
+/// Raw SMBIOS structure table exported by the kernel, readable by root only
+const DMI_TABLE: &str = "/sys/firmware/dmi/tables/DMI";
+
+const MEMORY_DEVICE: u8 = 17;
+const END_OF_TABLE: u8 = 127;
+
+/// Populated DIMM slot, from an SMBIOS type 17 (Memory Device) structure
+#[derive(Debug, PartialEq)]
+pub struct MemoryDevice {
+    /// Slot name as printed on the board, e.g. "DIMM_A1"
+    pub locator: String,
+    pub size_mb: u64,
+    /// Configured speed, or the rated speed when the firmware leaves it out,
+    /// in MT/s
+    pub speed_mts: Option<u32>,
+    /// Data width in bits, without ECC bits
+    pub data_width: Option<u32>,
+    pub memory_type: &'static str,
+}
+
+/// DIMMs described by the firmware
+pub fn memory_devices() -> std::io::Result<Vec<MemoryDevice>> {
+    Ok(parse_memory_devices(&fs::read(DMI_TABLE)?))
+}
+
+/// Peak memory bandwidth of the installed DIMMs in GB/s, assuming every
+/// DIMM has a channel of its own
+pub fn max_mem_bw_gbps(devices: &[MemoryDevice]) -> Option<f64> {
+    let total: f64 = devices
+        .iter()
+        .filter_map(|device| Some(device.speed_mts? as f64 * device.data_width? as f64 / 8.0))
+        .sum();
+    (total > 0.0).then_some(total / 1000.0)
+}
+
+fn memory_type(code: u8) -> &'static str {
+    match code {
+        0x12 => "DDR",
+        0x13 => "DDR2",
+        0x18 => "DDR3",
+        0x1A => "DDR4",
+        0x1B => "LPDDR",
+        0x1C => "LPDDR2",
+        0x1D => "LPDDR3",
+        0x1E => "LPDDR4",
+        0x22 => "DDR5",
+        0x23 => "LPDDR5",
+        _ => "Unknown",
+    }
+}
+
+fn parse_memory_devices(table: &[u8]) -> Vec<MemoryDevice> {
+    let mut devices = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= table.len() {
+        let kind = table[offset];
+        let length = table[offset + 1] as usize;
+        if length < 4 || offset + length > table.len() {
+            break;
+        }
+        let formatted = &table[offset..offset + length];
+        // Strings follow the formatted area and end with an empty one
+        let strings_start = offset + length;
+        let strings_end = table[strings_start..]
+            .windows(2)
+            .position(|pair| pair == [0, 0])
+            .map_or(table.len(), |end| strings_start + end);
+        let strings: Vec<&[u8]> = table[strings_start..strings_end].split(|&byte| byte == 0).collect();
+        offset = strings_end + 2;
+
+        if kind == END_OF_TABLE {
+            break;
+        }
+        if kind != MEMORY_DEVICE {
+            continue;
+        }
+        if let Some(device) = parse_memory_device(formatted, &strings) {
+            devices.push(device);
+        }
+    }
+    devices
+}
+
+/// None for empty slots
+fn parse_memory_device(formatted: &[u8], strings: &[&[u8]]) -> Option<MemoryDevice> {
+    let byte = |at: usize| formatted.get(at).copied();
+    let word = |at: usize| Some(u16::from_le_bytes([byte(at)?, byte(at + 1)?]));
+    let dword = |at: usize| Some(u32::from_le_bytes([byte(at)?, byte(at + 1)?, byte(at + 2)?, byte(at + 3)?]));
+    // String numbers start at 1, 0 means none
+    let string = |at: usize| {
+        let index = byte(at)? as usize;
+        let bytes = strings.get(index.checked_sub(1)?)?;
+        Some(String::from_utf8_lossy(bytes).trim().to_string())
+    };
+    // 0 and 0xFFFF mean unknown
+    let known = |value: u16| (value != 0 && value != 0xFFFF).then_some(value as u32);
+
+    let size_mb = match word(0x0C)? {
+        0 | 0xFFFF => return None,
+        0x7FFF => dword(0x1C)? as u64 & 0x7FFF_FFFF,
+        size if size & 0x8000 != 0 => (size & 0x7FFF) as u64 / 1024,
+        size => size as u64,
+    };
+    let speed_mts = word(0x20).and_then(known).or_else(|| word(0x15).and_then(known));
+    Some(MemoryDevice {
+        locator: string(0x10).unwrap_or_default(),
+        size_mb,
+        speed_mts,
+        data_width: word(0x0A).and_then(known),
+        memory_type: byte(0x12).map_or("Unknown", memory_type),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Type 17 structure of an SMBIOS 3.2 table
+    fn memory_device(size: u16, speed: u16, configured_speed: u16, locator: &str) -> Vec<u8> {
+        let mut structure = vec![0; 0x28];
+        structure[0] = MEMORY_DEVICE;
+        structure[1] = 0x28;
+        structure[0x0A..0x0C].copy_from_slice(&64u16.to_le_bytes());
+        structure[0x0C..0x0E].copy_from_slice(&size.to_le_bytes());
+        structure[0x10] = 1;
+        structure[0x12] = 0x22;
+        structure[0x15..0x17].copy_from_slice(&speed.to_le_bytes());
+        structure[0x20..0x22].copy_from_slice(&configured_speed.to_le_bytes());
+        structure.extend_from_slice(locator.as_bytes());
+        structure.extend_from_slice(&[0, 0]);
+        structure
+    }
+
+    #[test]
+    fn parses_populated_slots() {
+        let mut table = memory_device(16384, 4800, 4400, "DIMM_A1");
+        table.extend(memory_device(0, 0, 0, "DIMM_A2"));
+        table.extend([END_OF_TABLE, 4, 0, 0, 0, 0]);
+        assert_eq!(
+            parse_memory_devices(&table),
+            vec![MemoryDevice {
+                locator: "DIMM_A1".to_string(),
+                size_mb: 16384,
+                speed_mts: Some(4400),
+                data_width: Some(64),
+                memory_type: "DDR5",
+            }]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_rated_speed() {
+        let table = memory_device(16384, 3200, 0, "");
+        let devices = parse_memory_devices(&table);
+        assert_eq!(devices[0].speed_mts, Some(3200));
+        assert_eq!(devices[0].locator, "");
+        assert_eq!(max_mem_bw_gbps(&devices), Some(25.6));
+    }
+
+    #[test]
+    fn stops_at_truncated_structures() {
+        let mut table = memory_device(16384, 3200, 0, "DIMM_B1");
+        table.truncate(0x10);
+        assert!(parse_memory_devices(&table).is_empty());
+    }
+}
@@ -0,0 +1,24 @@
+use std::fmt;
+
+/// Collection failures the caller reacts to specifically; any other failure
+/// is a plain boxed error
+#[derive(Debug)]
+pub enum CollectionError {
+    /// AMDuProfPcm refuses to run until its license agreement is accepted
+    LicenseNotAccepted(String),
+    /// The report does not match the SHA-256 of its `.sha256` sidecar
+    ChecksumMismatch(String),
+}
+
+impl fmt::Display for CollectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollectionError::LicenseNotAccepted(message) => {
+                write!(f, "AMD uProf license not accepted: {}", message)
+            }
+            CollectionError::ChecksumMismatch(report) => write!(f, "checksum mismatch for {}", report),
+        }
+    }
+}
+
+impl std::error::Error for CollectionError {}
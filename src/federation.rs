@@ -0,0 +1,393 @@
+use futures_util::future::join_all;
+use hyper::client::HttpConnector;
+use hyper::{Client, Uri};
+use prometheus::proto::{self, LabelPair, Metric, MetricFamily, MetricType};
+use prometheus::{GaugeVec, Opts};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tokio::time;
+
+/// Short enough to finish within one collection interval
+const SCRAPE_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Tells the series of the upstreams apart, so that two of them reporting
+/// the same `nodename` do not collide
+const UPSTREAM_LABEL: &str = "upstream";
+
+struct Upstream {
+    uri: Uri,
+    /// `host:port`, the value of the `upstream` label
+    name: String,
+}
+
+/// Scrapes other exporters instead of running AMDuProfPcm and serves the
+/// union of their metrics
+pub struct Federation {
+    upstreams: Vec<Upstream>,
+    client: Client<HttpConnector>,
+    families: RwLock<Vec<MetricFamily>>,
+    up: GaugeVec,
+    /// Nodenames already reported at more than one upstream
+    conflicts: Mutex<HashSet<String>>,
+}
+
+impl Federation {
+    pub fn new(urls: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        if urls.is_empty() {
+            return Err("--federation-mode needs --upstream-urls".into());
+        }
+        let upstreams = urls
+            .iter()
+            .map(|url| {
+                let uri: Uri = url.parse().map_err(|e| format!("invalid upstream URL {}: {}", url, e))?;
+                if uri.scheme_str() != Some("http") {
+                    return Err(format!("only http:// upstreams are supported, not {}", url));
+                }
+                // A bare host:port means its /metrics
+                let uri = match uri.path() {
+                    "" | "/" if uri.query().is_none() => format!("{}metrics", uri).parse().unwrap(),
+                    _ => uri,
+                };
+                let name = uri.authority().map(ToString::to_string).unwrap_or_default();
+                Ok(Upstream { uri, name })
+            })
+            .collect::<Result<_, _>>()?;
+        let up = GaugeVec::new(
+            Opts::new("amd_uprof_federation_upstream_up", "1 if the last scrape of the upstream exporter succeeded"),
+            &[UPSTREAM_LABEL],
+        ).unwrap();
+        Ok(Self {
+            upstreams,
+            client: Client::new(),
+            families: RwLock::new(Vec::new()),
+            up,
+            conflicts: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub fn up(&self) -> &GaugeVec {
+        &self.up
+    }
+
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.families.read().unwrap().clone()
+    }
+
+    /// Scrapes every upstream concurrently; the series of upstreams that
+    /// failed are dropped, and the scrape fails only if all of them did
+    pub async fn scrape(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let bodies = join_all(self.upstreams.iter().map(|upstream| self.fetch(upstream))).await;
+        let mut merged: Vec<MetricFamily> = Vec::new();
+        let mut nodenames: HashMap<String, &str> = HashMap::new();
+        let mut succeeded = 0;
+        for (upstream, body) in self.upstreams.iter().zip(bodies) {
+            let families = body.and_then(|body| parse(&body));
+            self.up.with_label_values(&[&upstream.name]).set(if families.is_ok() { 1.0 } else { 0.0 });
+            let mut families = match families {
+                Ok(families) => families,
+                Err(e) => {
+                    eprintln!("Error scraping upstream {}: {}", upstream.name, e);
+                    continue;
+                }
+            };
+            succeeded += 1;
+            for metric in families.iter_mut().flat_map(|family| family.mut_metric().iter_mut()) {
+                self.note_nodename(metric, &upstream.name, &mut nodenames);
+                // Chained federation keeps the label of the first level
+                if !metric.get_label().iter().any(|label| label.get_name() == UPSTREAM_LABEL) {
+                    metric.mut_label().push(label_pair(UPSTREAM_LABEL, &upstream.name));
+                }
+            }
+            merge(&mut merged, families, &upstream.name);
+        }
+        if succeeded == 0 {
+            return Err("no upstream could be scraped".into());
+        }
+        merged.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+        *self.families.write().unwrap() = merged;
+        Ok(())
+    }
+
+    async fn fetch(&self, upstream: &Upstream) -> Result<String, String> {
+        let request = async {
+            let response = self.client.get(upstream.uri.clone()).await.map_err(|e| e.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("answered {}", response.status()));
+            }
+            let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+            String::from_utf8(body.to_vec()).map_err(|e| e.to_string())
+        };
+        time::timeout(SCRAPE_TIMEOUT, request)
+            .await
+            .unwrap_or_else(|_| Err("timed out".to_string()))
+    }
+
+    fn note_nodename<'a>(&self, metric: &Metric, upstream: &'a str, nodenames: &mut HashMap<String, &'a str>) {
+        let Some(nodename) = metric.get_label().iter().find(|label| label.get_name() == "nodename") else {
+            return;
+        };
+        let first = *nodenames.entry(nodename.get_value().to_string()).or_insert(upstream);
+        if first != upstream && self.conflicts.lock().unwrap().insert(nodename.get_value().to_string()) {
+            eprintln!(
+                "Warning: nodename {} is reported by upstreams {} and {}, told apart by the upstream label",
+                nodename.get_value(),
+                first,
+                upstream
+            );
+        }
+    }
+}
+
+/// Adds the families of one upstream to the union; a family whose type
+/// differs from the one already merged is left out
+fn merge(merged: &mut Vec<MetricFamily>, families: Vec<MetricFamily>, upstream: &str) {
+    for mut family in families {
+        match merged.iter_mut().find(|merged| merged.get_name() == family.get_name()) {
+            Some(existing) if existing.get_field_type() != family.get_field_type() => {
+                eprintln!(
+                    "Warning: upstream {} exports {} as {:?}, not {:?}, dropping it",
+                    upstream,
+                    family.get_name(),
+                    family.get_field_type(),
+                    existing.get_field_type()
+                );
+            }
+            Some(existing) => existing.mut_metric().extend(family.take_metric()),
+            None => merged.push(family),
+        }
+    }
+}
+
+fn label_pair(name: &str, value: &str) -> LabelPair {
+    let mut pair = LabelPair::default();
+    pair.set_name(name.to_string());
+    pair.set_value(value.to_string());
+    pair
+}
+
+/// Parses the Prometheus text format; sample timestamps are dropped and
+/// untyped metrics become gauges, which the text encoder cannot tell apart
+pub fn parse(text: &str) -> Result<Vec<MetricFamily>, String> {
+    let mut families: Vec<MetricFamily> = Vec::new();
+    // Histogram and summary samples sharing their labels form one metric
+    let mut grouped: HashMap<(usize, Labels), usize> = HashMap::new();
+    let mut current: Option<usize> = None;
+    let family_index = |families: &mut Vec<MetricFamily>, name: &str| {
+        families.iter().position(|family| family.get_name() == name).unwrap_or_else(|| {
+            let mut family = MetricFamily::default();
+            family.set_name(name.to_string());
+            family.set_field_type(MetricType::GAUGE);
+            families.push(family);
+            families.len() - 1
+        })
+    };
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.trim_start().splitn(3, ' ');
+            match (words.next(), words.next(), words.next()) {
+                (Some("HELP"), Some(name), help) => {
+                    let index = family_index(&mut families, name);
+                    families[index].set_help(unescape(help.unwrap_or_default()));
+                }
+                (Some("TYPE"), Some(name), Some(kind)) => {
+                    let index = family_index(&mut families, name);
+                    families[index].set_field_type(match kind.trim() {
+                        "counter" => MetricType::COUNTER,
+                        "gauge" => MetricType::GAUGE,
+                        "histogram" => MetricType::HISTOGRAM,
+                        "summary" => MetricType::SUMMARY,
+                        _ => MetricType::GAUGE,
+                    });
+                    current = Some(index);
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let (name, labels, value) = parse_sample(line).ok_or_else(|| format!("line {}: malformed sample", number + 1))?;
+        // Histogram and summary samples carry a suffix after the family name
+        let suffix = current.and_then(|index| {
+            let family = &families[index];
+            let kind = family.get_field_type();
+            if kind != MetricType::HISTOGRAM && kind != MetricType::SUMMARY {
+                return None;
+            }
+            let suffix = name.strip_prefix(family.get_name())?;
+            ["", "_bucket", "_sum", "_count"].contains(&suffix).then_some((index, suffix))
+        });
+        let Some((index, suffix)) = suffix else {
+            let index = family_index(&mut families, name);
+            let mut metric = Metric::default();
+            metric.set_label(labels.iter().map(|(name, value)| label_pair(name, value)).collect());
+            match families[index].get_field_type() {
+                MetricType::COUNTER => metric.mut_counter().set_value(value),
+                _ => metric.mut_gauge().set_value(value),
+            }
+            families[index].mut_metric().push(metric);
+            continue;
+        };
+
+        let key_label = if suffix == "_bucket" { "le" } else { "quantile" };
+        let bound = labels.iter().find(|(name, _)| name == key_label).map(|(_, value)| value.clone());
+        let labels: Labels = labels.into_iter().filter(|(name, _)| name != key_label).collect();
+        let is_histogram = families[index].get_field_type() == MetricType::HISTOGRAM;
+        let metrics = families[index].mut_metric();
+        let position = *grouped.entry((index, labels.clone())).or_insert_with(|| {
+            let mut metric = Metric::default();
+            metric.set_label(labels.iter().map(|(name, value)| label_pair(name, value)).collect());
+            metrics.push(metric);
+            metrics.len() - 1
+        });
+        let metric = &mut metrics[position];
+        let bound = || -> Result<f64, String> {
+            bound
+                .as_deref()
+                .and_then(|bound| bound.parse().ok())
+                .ok_or_else(|| format!("line {}: missing or invalid {}", number + 1, key_label))
+        };
+        match (is_histogram, suffix) {
+            // The encoders add the +Inf bucket from the count
+            (true, "_bucket") if bound()?.is_infinite() => {}
+            (true, "_bucket") => {
+                let mut bucket = proto::Bucket::default();
+                bucket.set_upper_bound(bound()?);
+                bucket.set_cumulative_count(value as u64);
+                metric.mut_histogram().mut_bucket().push(bucket);
+            }
+            (true, "_sum") => metric.mut_histogram().set_sample_sum(value),
+            (true, "_count") => metric.mut_histogram().set_sample_count(value as u64),
+            (false, "") => {
+                let mut quantile = proto::Quantile::default();
+                quantile.set_quantile(bound()?);
+                quantile.set_value(value);
+                metric.mut_summary().mut_quantile().push(quantile);
+            }
+            (false, "_sum") => metric.mut_summary().set_sample_sum(value),
+            (false, "_count") => metric.mut_summary().set_sample_count(value as u64),
+            _ => return Err(format!("line {}: unexpected sample {}", number + 1, name)),
+        }
+    }
+    // Families announced without samples cannot be encoded again
+    families.retain(|family| !family.get_metric().is_empty());
+    Ok(families)
+}
+
+type Labels = Vec<(String, String)>;
+
+/// `name{label="value",...} value [timestamp]`
+fn parse_sample(line: &str) -> Option<(&str, Labels, f64)> {
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())?;
+    let name = &line[..name_end];
+    let mut rest = &line[name_end..];
+    let mut labels = Vec::new();
+    if let Some(mut inner) = rest.strip_prefix('{') {
+        loop {
+            inner = inner.trim_start_matches([' ', ',']);
+            if let Some(after) = inner.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (label, after) = inner.split_once('=')?;
+            let mut chars = after.trim_start().strip_prefix('"')?.char_indices();
+            let mut value = String::new();
+            let end = loop {
+                match chars.next()? {
+                    (_, '\\') => match chars.next()?.1 {
+                        'n' => value.push('\n'),
+                        escaped => value.push(escaped),
+                    },
+                    (at, '"') => break at,
+                    (_, c) => value.push(c),
+                }
+            };
+            labels.push((label.trim().to_string(), value));
+            inner = &after.trim_start()[1 + end + 1..];
+        }
+    }
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some((name, labels, value))
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Encoder, TextEncoder};
+
+    const EXPOSITION: &str = "\
+# HELP amd_l3_miss_percent L3 Miss %
+# TYPE amd_l3_miss_percent gauge
+amd_l3_miss_percent{nodename=\"node-1\",zone=\"a \\\"b\\\", c\"} 20.5
+# HELP amd_uprof_http_request_duration_seconds Time to serve an HTTP request
+# TYPE amd_uprof_http_request_duration_seconds histogram
+amd_uprof_http_request_duration_seconds_bucket{path=\"/metrics\",le=\"0.1\"} 3
+amd_uprof_http_request_duration_seconds_bucket{path=\"/metrics\",le=\"+Inf\"} 4
+amd_uprof_http_request_duration_seconds_sum{path=\"/metrics\"} 0.5
+amd_uprof_http_request_duration_seconds_count{path=\"/metrics\"} 4
+# TYPE amd_empty gauge
+untyped_metric 1 1700000000000
+";
+
+    #[test]
+    fn round_trips_the_text_format() {
+        let families = parse(EXPOSITION).unwrap();
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&families, &mut buffer).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "\
+# HELP amd_l3_miss_percent L3 Miss %
+# TYPE amd_l3_miss_percent gauge
+amd_l3_miss_percent{nodename=\"node-1\",zone=\"a \\\"b\\\", c\"} 20.5
+# HELP amd_uprof_http_request_duration_seconds Time to serve an HTTP request
+# TYPE amd_uprof_http_request_duration_seconds histogram
+amd_uprof_http_request_duration_seconds_bucket{path=\"/metrics\",le=\"0.1\"} 3
+amd_uprof_http_request_duration_seconds_bucket{path=\"/metrics\",le=\"+Inf\"} 4
+amd_uprof_http_request_duration_seconds_sum{path=\"/metrics\"} 0.5
+amd_uprof_http_request_duration_seconds_count{path=\"/metrics\"} 4
+# TYPE untyped_metric gauge
+untyped_metric 1
+"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_samples() {
+        assert!(parse("amd_l3_miss_percent{nodename=\"node-1} 20.5").is_err());
+        assert!(parse("amd_l3_miss_percent twenty").is_err());
+    }
+
+    #[test]
+    fn merges_upstreams_of_the_same_type() {
+        let mut merged = parse("# TYPE a gauge\na{nodename=\"x\"} 1").unwrap();
+        merge(&mut merged, parse("# TYPE a gauge\na{nodename=\"y\"} 2").unwrap(), "y:9100");
+        merge(&mut merged, parse("# TYPE a counter\na{nodename=\"z\"} 3").unwrap(), "z:9100");
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].get_metric().len(), 2);
+    }
+}
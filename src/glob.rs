@@ -0,0 +1,35 @@
+/// Glob match where `*` is the only special character
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        assert!(matches("gpu-*", "gpu-01"));
+        assert!(matches("*", ""));
+        assert!(matches("rack*-node*", "rack2-node17"));
+        assert!(matches("node-1", "node-1"));
+        assert!(!matches("node-1", "node-10"));
+        assert!(!matches("gpu-*", "cpu-01"));
+        assert!(!matches("ab*ba", "aba"));
+    }
+}
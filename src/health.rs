@@ -0,0 +1,42 @@
+use crate::admin::text_response;
+use hyper::{Body, Response, StatusCode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Whether the collection loop is waiting for its `--schedule` window
+#[derive(Default)]
+pub struct Readiness {
+    idle: AtomicBool,
+}
+
+impl Readiness {
+    pub fn set_idle(&self, idle: bool) {
+        self.idle.store(idle, Ordering::Relaxed);
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle.load(Ordering::Relaxed)
+    }
+}
+
+/// Ready after the first successful collection; outside the `--schedule`
+/// window the exporter reports itself ready but idle
+pub async fn readyz_handler(
+    readiness: &Readiness,
+    last_collection: Option<Instant>,
+    collection_errors: u64,
+) -> Result<Response<Body>, hyper::Error> {
+    let response = if readiness.idle.load(Ordering::Relaxed) {
+        text_response(StatusCode::OK, "scheduled but idle")
+    } else if let Some(last_collection) = last_collection {
+        let message = format!(
+            "ready, last collection {}s ago, {} failed collections",
+            last_collection.elapsed().as_secs(),
+            collection_errors
+        );
+        text_response(StatusCode::OK, &message)
+    } else {
+        text_response(StatusCode::SERVICE_UNAVAILABLE, "waiting for the first collection")
+    };
+    Ok(response)
+}
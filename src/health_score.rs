@@ -0,0 +1,128 @@
+use crate::collectors::dimm_temp;
+use crate::columns;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+use serde::Deserialize;
+
+/// `[health_score]` section: weight of each component in the score
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthScoreConfig {
+    pub l3_weight: f64,
+    pub ic_weight: f64,
+    pub bw_weight: f64,
+    pub l3_latency_weight: f64,
+    pub dimm_temp_weight: f64,
+    /// Memory bandwidth counted as fully used, --max-mem-bw-gbps or the
+    /// detected peak by default; without either the bandwidth component is
+    /// left out
+    pub max_mem_bw_gbps: Option<f64>,
+    /// L3 miss latency counted as worst; without it the latency component
+    /// is left out
+    pub max_l3_miss_latency_ns: Option<f64>,
+    /// Temperature of the hottest DIMM counted as worst
+    pub dimm_temp_limit_celsius: f64,
+}
+
+impl Default for HealthScoreConfig {
+    fn default() -> Self {
+        Self {
+            l3_weight: 1.0,
+            ic_weight: 1.0,
+            bw_weight: 1.0,
+            l3_latency_weight: 1.0,
+            dimm_temp_weight: 1.0,
+            max_mem_bw_gbps: None,
+            max_l3_miss_latency_ns: None,
+            // JEDEC normal operating range of DDR4 and DDR5
+            dimm_temp_limit_celsius: 85.0,
+        }
+    }
+}
+
+/// Weighted average of L3 hits, instruction cache hits, free memory
+/// bandwidth, L3 miss latency and DIMM temperature, between 0 (bad) and 1
+/// (good). Hot DIMMs throttle and show up as longer L3 miss latency, so
+/// both count towards the score.
+pub struct HealthScore {
+    nodename: String,
+    config: HealthScoreConfig,
+    score: GaugeVec,
+    l3: GaugeVec,
+    ic: GaugeVec,
+    bw: GaugeVec,
+    l3_latency: GaugeVec,
+    dimm_temp: GaugeVec,
+}
+
+impl HealthScore {
+    pub fn new(registry: &Registrar, nodename: &str, config: HealthScoreConfig) -> Self {
+        let gauge = |name: &str, help: &str| {
+            let gauge = GaugeVec::new(Opts::new(name, help), &["nodename"]).unwrap();
+            registry.register(&gauge);
+            gauge
+        };
+        Self {
+            nodename: nodename.to_string(),
+            config,
+            score: gauge("amd_uprof_health_score", "Weighted average of the health components, 1 is best"),
+            l3: gauge("amd_uprof_health_component_l3", "1 - L3 miss percent / 100"),
+            ic: gauge("amd_uprof_health_component_ic", "1 - IC fetch miss ratio"),
+            bw: gauge("amd_uprof_health_component_bw", "1 - total memory bandwidth / max_mem_bw_gbps"),
+            l3_latency: gauge(
+                "amd_uprof_health_component_l3_latency",
+                "1 - average L3 miss latency / max_l3_miss_latency_ns",
+            ),
+            dimm_temp: gauge(
+                "amd_uprof_health_component_dimm_temp",
+                "1 - hottest DIMM temperature / dimm_temp_limit_celsius",
+            ),
+        }
+    }
+
+    /// Recomputes the score from a collected row; components whose column
+    /// was not collected, or without DIMM sensors, are left out of the average
+    pub fn update(&self, values: &[f64]) {
+        // Out of bounds values are dropped by `Metrics::update` as well
+        let value = |column: usize| {
+            let (min, max) = columns::bounds(column);
+            Some(values[column]).filter(|value| (min..=max).contains(value)).unwrap_or(f64::NAN)
+        };
+        let max_mem_bw = self.config.max_mem_bw_gbps.unwrap_or(f64::NAN);
+        let max_latency = self.config.max_l3_miss_latency_ns.unwrap_or(f64::NAN);
+        let hottest_dimm = dimm_temp::dimm_temperatures()
+            .into_iter()
+            .map(|(_, celsius)| celsius)
+            .reduce(f64::max)
+            .unwrap_or(f64::NAN);
+        let components = [
+            (&self.l3, 1.0 - value(columns::L3_MISS_PERCENT) / 100.0, self.config.l3_weight),
+            (&self.ic, 1.0 - value(columns::IC_FETCH_MISS_RATIO), self.config.ic_weight),
+            (&self.bw, 1.0 - value(columns::TOTAL_MEM_BW_GBPS) / max_mem_bw, self.config.bw_weight),
+            (
+                &self.l3_latency,
+                1.0 - value(columns::AVE_L3_MISS_LATENCY_NS) / max_latency,
+                self.config.l3_latency_weight,
+            ),
+            (
+                &self.dimm_temp,
+                1.0 - hottest_dimm / self.config.dimm_temp_limit_celsius,
+                self.config.dimm_temp_weight,
+            ),
+        ];
+        let labels = [self.nodename.as_str()];
+        let (mut sum, mut weights) = (0.0, 0.0);
+        for (gauge, component, weight) in components {
+            if component.is_nan() {
+                continue;
+            }
+            let component = component.clamp(0.0, 1.0);
+            gauge.with_label_values(&labels).set(component);
+            sum += component * weight;
+            weights += weight;
+        }
+        if weights > 0.0 {
+            self.score.with_label_values(&labels).set(sum / weights);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::label::sanitize_label_value;
+use prometheus::proto::{LabelPair, MetricFamily};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time;
+
+/// Script run before every collection that prints `phase=<value>` to tag
+/// the cycle with the current workload phase
+pub struct PreCollectHook {
+    path: PathBuf,
+    timeout: Duration,
+}
+
+impl PreCollectHook {
+    pub fn new(path: PathBuf, timeout: Duration) -> Self {
+        Self { path, timeout }
+    }
+
+    /// Phase printed by the script; a failing or slow hook yields none and
+    /// collection goes ahead without it
+    pub async fn run(&self) -> Option<String> {
+        let output = Command::new(&self.path).kill_on_drop(true).output();
+        let output = match time::timeout(self.timeout, output).await {
+            Ok(Ok(output)) if output.status.success() => output,
+            Ok(Ok(output)) => {
+                eprintln!("Pre-collect hook {} failed: {}", self.path.display(), output.status);
+                return None;
+            }
+            Ok(Err(e)) => {
+                eprintln!("Failed to run pre-collect hook {}: {}", self.path.display(), e);
+                return None;
+            }
+            Err(_) => {
+                eprintln!("Pre-collect hook {} timed out", self.path.display());
+                return None;
+            }
+        };
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("phase="))
+            .map(|phase| phase.trim().to_string())
+            .filter(|phase| !phase.is_empty())
+    }
+}
+
+/// Phase of the last collection cycle, attached to every sample as `phase`
+#[derive(Default)]
+pub struct Phase(RwLock<Option<String>>);
+
+impl Phase {
+    pub fn set(&self, phase: Option<String>) {
+        *self.0.write().unwrap() = phase;
+    }
+
+    pub fn apply(&self, families: &mut [MetricFamily]) {
+        let Some(phase) = self.0.read().unwrap().clone() else {
+            return;
+        };
+        let mut label = LabelPair::new();
+        label.set_name("phase".to_string());
+        label.set_value(sanitize_label_value(&phase));
+        for metric in families.iter_mut().flat_map(|family| family.mut_metric().iter_mut()) {
+            metric.mut_label().push(label.clone());
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::columns;
+use crate::subprocess::{self, SubprocessThread};
+use std::collections::HashMap;
+use tokio::fs;
+use std::process::Command;
+
+const PCM_OUTPUT: &str = "/var/uprof/pcm.csv";
+const PCM_MEMORY_OUTPUT: &str = "/var/uprof/pcm_memory.csv";
+
+/// Runs Intel's `pcm` and `pcm-memory` for one second each and maps their
+/// system-wide columns onto the uProf row layout
+pub async fn collect_metrics(
+    subprocess: Option<&SubprocessThread>,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let core = run_pcm("pcm", PCM_OUTPUT, subprocess).await?;
+    let memory = run_pcm("pcm-memory", PCM_MEMORY_OUTPUT, subprocess).await?;
+
+    let mut values = vec![f64::NAN; columns::COUNT];
+    let core = |name: &str| core.get(name).copied().unwrap_or(f64::NAN);
+    let memory = |name: &str| memory.get(name).copied().unwrap_or(f64::NAN);
+
+    // Misses per instruction and hit ratios are enough to rebuild L2 access/hit pti
+    let l2_miss_pti = core("L2MPI") * 1000.0;
+    let l2_access_pti = l2_miss_pti / (1.0 - core("L2HIT"));
+    values[columns::L2_ACCESS_PTI] = l2_access_pti;
+    values[columns::L2_MISS_PTI] = l2_miss_pti;
+    values[columns::L2_HIT_PTI] = l2_access_pti - l2_miss_pti;
+
+    values[columns::L3_HIT_PERCENT] = core("L3HIT") * 100.0;
+    values[columns::L3_MISS_PERCENT] = 100.0 - values[columns::L3_HIT_PERCENT];
+
+    // pcm-memory reports MB/s
+    values[columns::TOTAL_MEM_RDBW_GBPS] = memory("Read") / 1000.0;
+    values[columns::TOTAL_MEM_WRBW_GBPS] = memory("Write") / 1000.0;
+    values[columns::TOTAL_MEM_BW_GBPS] = memory("Memory") / 1000.0;
+
+    Ok(values)
+}
+
+async fn run_pcm(
+    tool: &str,
+    output_path: &str,
+    subprocess: Option<&SubprocessThread>,
+) -> Result<HashMap<String, f64>, Box<dyn std::error::Error>> {
+    let mut command = Command::new(tool);
+    command.args([format!("-csv={}", output_path).as_str(), "-i=1", "1"]);
+    let output = subprocess::output(command, subprocess).await?;
+
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", tool, String::from_utf8_lossy(&output.stderr)).into());
+    }
+
+    let content = fs::read_to_string(output_path).await?;
+    let _ = fs::remove_file(output_path).await;
+
+    parse_system_columns(&content).ok_or_else(|| format!("Failed to parse {} output", tool).into())
+}
+
+/// PCM CSV files have a group header row followed by a column header row;
+/// picks the columns of the `System` group from the last data row
+fn parse_system_columns(content: &str) -> Option<HashMap<String, f64>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(content.as_bytes());
+    let rows: Vec<csv::StringRecord> = reader.records().filter_map(Result::ok).collect();
+    if rows.len() < 3 {
+        return None;
+    }
+
+    let mut group = "";
+    let mut values = HashMap::new();
+    for (i, name) in rows[1].iter().enumerate() {
+        match rows[0].get(i).map(str::trim) {
+            Some(g) if !g.is_empty() => group = g,
+            _ => {}
+        }
+        if group != "System" {
+            continue;
+        }
+        if let Some(Ok(value)) = rows.last()?.get(i).map(|v| v.trim().parse()) {
+            values.insert(name.trim().to_string(), value);
+        }
+    }
+    Some(values)
+}
@@ -0,0 +1,199 @@
+use crate::label::{sanitize_label_value, validate_label_name};
+use hyper::{Body, Request};
+use prometheus::proto::{LabelPair, MetricFamily};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_rustls::rustls::{Certificate, ClientConfig, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+/// Downward API environment variables and the labels they become
+const DOWNWARD_API_ENV: [(&str, &str); 3] = [
+    ("POD_NAME", "pod"),
+    ("POD_NAMESPACE", "namespace"),
+    ("NODE_NAME", "node"),
+];
+
+/// Pod metadata injected by a DaemonSet through the Downward API
+pub fn downward_api_labels() -> HashMap<String, String> {
+    DOWNWARD_API_ENV
+        .iter()
+        .filter_map(|(env, label)| {
+            let value = std::env::var(env).ok().filter(|value| !value.is_empty())?;
+            Some((label.to_string(), sanitize_label_value(&value)))
+        })
+        .collect()
+}
+
+const NODE_LABELS_REFRESH: Duration = Duration::from_secs(300);
+const NODE_LABELS_TIMEOUT: Duration = Duration::from_secs(10);
+const SERVICE_ACCOUNT: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+/// Node labels such as `topology.kubernetes.io/zone`, read from the API
+/// server with the pod's service account and appended to every sample
+pub struct NodeLabels {
+    names: Vec<String>,
+    labels: RwLock<Vec<LabelPair>>,
+}
+
+impl NodeLabels {
+    pub fn new(names: Vec<String>) -> Arc<Self> {
+        Arc::new(Self {
+            names,
+            labels: RwLock::new(Vec::new()),
+        })
+    }
+
+    /// Refreshes the cached labels every five minutes; keeps the previous
+    /// ones when the API server cannot be reached
+    pub async fn run(self: Arc<Self>, node: String) {
+        if self.names.is_empty() {
+            return;
+        }
+        let mut interval = time::interval(NODE_LABELS_REFRESH);
+        loop {
+            interval.tick().await;
+            let fetched = time::timeout(NODE_LABELS_TIMEOUT, fetch_node_labels(&node))
+                .await
+                .unwrap_or_else(|_| Err("the API server did not answer in time".into()));
+            match fetched {
+                Ok(node_labels) => *self.labels.write().unwrap() = self.select(&node_labels),
+                Err(e) => eprintln!("Failed to fetch labels of node {}: {}", node, e),
+            }
+        }
+    }
+
+    fn select(&self, node_labels: &HashMap<String, String>) -> Vec<LabelPair> {
+        self.names
+            .iter()
+            .filter_map(|name| {
+                let mut pair = LabelPair::new();
+                pair.set_name(label_name(name));
+                pair.set_value(sanitize_label_value(node_labels.get(name)?));
+                Some(pair)
+            })
+            .collect()
+    }
+
+    pub fn apply(&self, families: &mut [MetricFamily]) {
+        let labels = self.labels.read().unwrap();
+        if labels.is_empty() {
+            return;
+        }
+        for metric in families.iter_mut().flat_map(|family| family.mut_metric().iter_mut()) {
+            metric.mut_label().extend(labels.iter().cloned());
+        }
+    }
+}
+
+/// `host:port`, with IPv6 addresses in brackets
+fn host_header(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+#[derive(Deserialize)]
+struct Node {
+    metadata: NodeMetadata,
+}
+
+#[derive(Deserialize)]
+struct NodeMetadata {
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+/// `GET /api/v1/nodes/<node>` on the in-cluster API server, trusting the
+/// service account's CA and authenticating with its token
+async fn fetch_node_labels(node: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error + Send + Sync>> {
+    let host = std::env::var("KUBERNETES_SERVICE_HOST").map_err(|_| "KUBERNETES_SERVICE_HOST is not set, not running in a pod")?;
+    let port: u16 = std::env::var("KUBERNETES_SERVICE_PORT").map_or(Ok(443), |port| port.parse())?;
+    let token = std::fs::read_to_string(format!("{}/token", SERVICE_ACCOUNT))?;
+
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(format!("{}/ca.crt", SERVICE_ACCOUNT))?))? {
+        roots.add(&Certificate(cert))?;
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    // An IPv6 service address comes bare, which both accept as it is
+    let server_name = ServerName::try_from(host.as_str())?;
+    let tcp = TcpStream::connect((host.as_str(), port)).await?;
+    let tls = TlsConnector::from(Arc::new(config)).connect(server_name, tcp).await?;
+
+    let (mut sender, connection) = hyper::client::conn::handshake(tls).await?;
+    tokio::spawn(connection);
+    let request = Request::get(format!("/api/v1/nodes/{}", node))
+        .header("Host", host_header(&host, port))
+        .header("Authorization", format!("Bearer {}", token.trim()))
+        .header("Accept", "application/json")
+        .body(Body::empty())?;
+    let response = sender.send_request(request).await?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    if !status.is_success() {
+        return Err(format!("API server answered {}: {}", status, String::from_utf8_lossy(&body)).into());
+    }
+
+    Ok(serde_json::from_slice::<Node>(&body)?.metadata.labels)
+}
+
+/// `topology.kubernetes.io/zone` becomes `topology_kubernetes_io_zone`
+pub fn label_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Labels the exporter sets itself; a node label of the same name would
+/// produce samples with the label twice
+const BUILT_IN_LABELS: &[&str] = &[
+    "nodename", "phase", "pod", "namespace", "node", "metric_name", "le", "quantile", "socket", "core", "type",
+    "slot", "bank", "numa_node", "zone", "window", "interface", "governor", "hugepagesize", "thp_mode", "mc",
+    "csrow", "channel", "pid", "comm", "size_mb", "speed_mhz", "path", "status", "version", "rustc_version",
+    "target", "vm", "bus", "link_id", "upstream",
+];
+
+/// Checks that the node labels become valid label names distinct from each
+/// other, from `reserved` and from the built-in labels
+pub fn validate_node_labels(names: &[String], reserved: &[&str]) -> Result<(), String> {
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    for name in names {
+        let label = label_name(name);
+        validate_label_name(&label)?;
+        if label.starts_with("__") || BUILT_IN_LABELS.contains(&label.as_str()) || reserved.contains(&label.as_str()) {
+            return Err(format!("{} becomes {}, which the exporter already uses", name, label));
+        }
+        if let Some(other) = seen.insert(label.clone(), name) {
+            return Err(format!("{} and {} both become {}", other, name, label));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_colliding_node_labels() {
+        let names = |names: &[&str]| names.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert!(validate_node_labels(&names(&["topology.kubernetes.io/zone"]), &[]).is_ok());
+        assert!(validate_node_labels(&names(&["nodename"]), &[]).is_err());
+        assert!(validate_node_labels(&names(&["example.com/phase"]), &[]).is_ok());
+        assert!(validate_node_labels(&names(&["phase"]), &[]).is_err());
+        assert!(validate_node_labels(&names(&["rack"]), &["rack"]).is_err());
+        assert!(validate_node_labels(&names(&["a.b", "a/b"]), &[]).is_err());
+        assert!(validate_node_labels(&names(&["__meta"]), &[]).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+/// Replaces every character outside `[a-zA-Z0-9_.-]` with `_`, so values
+/// read from the host cannot break the exposition format
+pub fn sanitize_label_value(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') { c } else { '_' })
+        .collect()
+}
+
+/// Checks a label name against `[a-zA-Z_][a-zA-Z0-9_]*`
+pub fn validate_label_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("label name is empty".to_string());
+    }
+    for (position, c) in name.chars().enumerate() {
+        let valid = c.is_ascii_alphabetic() || c == '_' || (position > 0 && c.is_ascii_digit());
+        if !valid {
+            return Err(format!(
+                "invalid label name {:?}: character {:?} at position {} is not allowed, \
+                 label names must match [a-zA-Z_][a-zA-Z0-9_]*",
+                name, c, position
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Parses a `name=value` constant label given on the command line
+pub fn parse_label_pair(pair: &str) -> Result<(String, String), String> {
+    let (name, value) = pair
+        .split_once('=')
+        .ok_or_else(|| format!("expected name=value, got {:?}", pair))?;
+    validate_label_name(name)?;
+    Ok((name.to_string(), sanitize_label_value(value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sanitize_label_value, validate_label_name};
+
+    #[test]
+    fn keeps_ordinary_hostnames() {
+        assert_eq!(sanitize_label_value("node-01.dc1.example.com"), "node-01.dc1.example.com");
+        assert_eq!(sanitize_label_value("EPYC_9654"), "EPYC_9654");
+    }
+
+    #[test]
+    fn replaces_exposition_syntax() {
+        assert_eq!(sanitize_label_value("host{a=\"b\"}"), "host_a__b__");
+        assert_eq!(sanitize_label_value("back\\slash"), "back_slash");
+    }
+
+    #[test]
+    fn replaces_whitespace_and_newlines() {
+        assert_eq!(sanitize_label_value("host\n"), "host_");
+        assert_eq!(sanitize_label_value("my host\tname"), "my_host_name");
+    }
+
+    #[test]
+    fn replaces_non_ascii_per_character() {
+        assert_eq!(sanitize_label_value("сервер-1"), "______-1");
+    }
+
+    #[test]
+    fn empty_stays_empty() {
+        assert_eq!(sanitize_label_value(""), "");
+    }
+
+    #[test]
+    fn accepts_valid_label_names() {
+        assert!(validate_label_name("datacenter").is_ok());
+        assert!(validate_label_name("_rack_2").is_ok());
+    }
+
+    #[test]
+    fn reports_invalid_character_position() {
+        let error = validate_label_name("my-label").unwrap_err();
+        assert!(error.contains("'-' at position 2"), "{}", error);
+        let error = validate_label_name("2nd").unwrap_err();
+        assert!(error.contains("'2' at position 0"), "{}", error);
+        assert!(validate_label_name("").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+
+const BACKLOG: i32 = 1024;
+
+/// Non-blocking listener for hyper. IPv6 sockets are dual-stack unless
+/// `ipv6_only` is set, whatever `net.ipv6.bindv6only` says.
+pub fn bind(addr: SocketAddr, ipv6_only: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Client, Request, Response, Server, Version};
+    use std::convert::Infallible;
+    use std::net::TcpStream;
+
+    #[tokio::test]
+    async fn serves_ipv6_loopback() {
+        let listener = bind("[::1]:0".parse().unwrap(), true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|_| async { Ok::<_, Infallible>(Response::new(Body::from("ok"))) }))
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_svc));
+
+        let uri = format!("http://{}/metrics", addr).parse().unwrap();
+        let response = Client::new().get(uri).await.unwrap();
+        assert!(response.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn serves_h2c_with_prior_knowledge() {
+        let listener = bind("127.0.0.1:0".parse().unwrap(), false).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+                Ok::<_, Infallible>(Response::new(Body::from(format!("{:?}", req.version()))))
+            }))
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_svc));
+
+        let uri = format!("http://{}/metrics", addr).parse().unwrap();
+        let response = Client::builder().http2_only(true).build_http::<Body>().get(uri).await.unwrap();
+        assert_eq!(response.version(), Version::HTTP_2);
+    }
+
+    #[test]
+    fn ipv6_wildcard_is_dual_stack_by_default() {
+        let listener = bind("[::]:0".parse().unwrap(), false).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_ok());
+    }
+
+    #[test]
+    fn ipv6_only_refuses_ipv4() {
+        let listener = bind("[::]:0".parse().unwrap(), true).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+        assert!(TcpStream::connect(("::1", port)).is_ok());
+    }
+}
@@ -1,397 +1,1225 @@
-use prometheus::{Encoder, GaugeVec, Registry, TextEncoder, Opts};
+mod access_log;
+mod admin;
+mod alerts;
+mod cli;
+mod collectors;
+mod columns;
+mod config;
+mod cpu;
+mod dmi;
+mod error;
+mod federation;
+mod glob;
+mod intel;
+mod health;
+mod health_score;
+mod hooks;
+mod k8s;
+mod label;
+mod listen;
+mod metrics;
+mod msr;
+mod openmetrics;
+mod output;
+mod perf;
+mod proxy;
+mod registration;
+mod schedule;
+mod snapshot;
+mod self_metrics;
+mod subprocess;
+mod tenant;
+mod transform;
+mod utilization;
+mod version_cache;
+mod web_config;
+
+use clap::Parser;
+use futures_util::future::try_join_all;
+use metrics::Metrics;
+use prometheus::proto::MetricFamily;
+use prometheus::{Encoder, IntCounter, Registry, TextEncoder};
+use rand::Rng;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::process::Command;
 use std::fs;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinSet;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::time;
+use tokio_rustls::TlsAcceptor;
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::HeaderValue;
+use hyper::server::conn::Http;
 use hyper::{
-    server::Server,
-    service::{make_service_fn, service_fn},
+    service::service_fn,
     Body, Request, Response, StatusCode,
 };
 
-struct Metrics {
-    registry: Registry,
-    nodename: String,
-    ic_fetch_miss_ratio: GaugeVec,
-    op_cache_fetch_miss_ratio: GaugeVec,
-    ic_access_pti: GaugeVec,
-    ic_miss_pti: GaugeVec,
-    dc_access_pti: GaugeVec,
-    l2_access_pti: GaugeVec,
-    l2_access_from_ic_miss_pti: GaugeVec,
-    l2_access_from_dc_miss_pti: GaugeVec,
-    l2_access_from_l2_hwpf_pti: GaugeVec,
-    l2_miss_pti: GaugeVec,
-    l2_miss_from_ic_miss_pti: GaugeVec,
-    l2_miss_from_dc_miss_pti: GaugeVec,
-    l2_miss_from_l2_hwpf_pti: GaugeVec,
-    l2_hit_pti: GaugeVec,
-    l2_hit_from_ic_miss_pti: GaugeVec,
-    l2_hit_from_dc_miss_pti: GaugeVec,
-    l2_hit_from_l2_hwpf_pti: GaugeVec,
-    l3_access: GaugeVec,
-    l3_miss: GaugeVec,
-    l3_miss_percent: GaugeVec,
-    l3_hit_percent: GaugeVec,
-    ave_l3_miss_latency_ns: GaugeVec,
-    total_mem_bw_gbps: GaugeVec,
-    local_dram_read_data_bytes_gbps: GaugeVec,
-    local_dram_write_data_bytes_gbps: GaugeVec,
-    remote_dram_read_data_bytes_gbps: GaugeVec,
-    remote_dram_write_data_bytes_gbps: GaugeVec,
-    total_mem_rdbw_gbps: GaugeVec,
-    total_mem_wrbw_gbps: GaugeVec,
+const UPROF_PCM: &str = "/opt/AMDuProf_Linux_x64_5.1.701/bin/AMDuProfPcm";
+
+/// The first of `sources` that yields a hostname
+fn get_host_hostname(sources: &[cli::NodenameSource]) -> String {
+    for source in sources {
+        let hostname = match source {
+            // Попытка получить hostname из переменной окружения
+            cli::NodenameSource::Env => std::env::var("HOST_HOSTNAME").ok(),
+            // Попытка прочитать из /host_hostname (если примонтирован файл)
+            cli::NodenameSource::HostHostnameFile => {
+                fs::read_to_string("/host_hostname").ok().map(|hostname| hostname.trim().to_string())
+            }
+            // Попытка прочитать из /etc/host_hostname
+            cli::NodenameSource::EtcFile => {
+                fs::read_to_string("/etc/host_hostname").ok().map(|hostname| hostname.trim().to_string())
+            }
+            // Попытка получить hostname контейнера
+            cli::NodenameSource::HostnameCmd => Command::new("hostname")
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string()),
+            cli::NodenameSource::Literal(value) => Some(value.clone()),
+        };
+        if let Some(hostname) = hostname {
+            return hostname;
+        }
+    }
+
+    "unknown".to_string()
 }
 
-fn get_host_hostname() -> String {
-    // Попытка получить hostname из переменной окружения
-    if let Ok(hostname) = std::env::var("HOST_HOSTNAME") {
-        return hostname;
+/// Last row of a report with exactly one value per column of its layout;
+/// rows of another length, e.g. cut short, and header rows are skipped
+fn parse_uprof_output(
+    report: &Report,
+    generation: Option<cpu::ZenGeneration>,
+    profiled: impl Fn(usize) -> bool,
+) -> Option<Vec<f64>> {
+    // Columns that are not exported are still part of the layout, but skipped
+    let exported = |column: Option<usize>| column.filter(|column| cpu::exports(generation, *column));
+    let layouts: Vec<Vec<Option<usize>>> = match &report.columns {
+        // Columns uProf described itself, unknown ones are skipped
+        Some(names) => vec![names.iter().map(|name| exported(metrics::column_of_header(name))).collect()],
+        // Older generations and columns that were not profiled are omitted, the rest keep their order
+        None => {
+            let layout = |reported: &dyn Fn(usize) -> bool| -> Vec<Option<usize>> {
+                (0..columns::COUNT)
+                    .filter(|column| reported(*column) && profiled(*column))
+                    .map(|column| exported(Some(column)))
+                    .collect()
+            };
+            match generation {
+                Some(generation) => vec![layout(&|column| generation.supports(column))],
+                // An undetected generation may or may not report its own columns
+                None => vec![layout(&|_| true), layout(&|column| !cpu::is_generation_specific(column))],
+            }
+        }
+    };
+    if layouts.iter().all(Vec::is_empty) {
+        return None;
+    }
+    let content = report.data.as_str().ok()?;
+
+    for line in content.lines().rev() {
+        if !line.contains(',') || line.contains("System") || line.contains("METRICS") {
+            continue;
+        }
+        let parts: Vec<Option<f64>> = line.split(',').map(|part| part.trim().parse().ok()).collect();
+        // Column names or a row of another set of profiles
+        let Some(layout) = layouts.iter().find(|layout| layout.len() == parts.len()) else {
+            continue;
+        };
+        if parts.iter().all(Option::is_none) {
+            continue;
+        }
+        let mut values = vec![f64::NAN; columns::COUNT];
+        for (part, column) in parts.iter().zip(layout) {
+            if let (Some(value), Some(column)) = (part, column) {
+                values[*column] = *value;
+            }
+        }
+        return Some(values);
     }
+    None
+}
+
+/// Longest `AMDuProfPcm --version` may take before startup gives up
+const VERSION_TIMEOUT: Duration = Duration::from_secs(10);
 
-    // Попытка прочитать из /host_hostname (если примонтирован файл)
-    if let Ok(hostname) = fs::read_to_string("/host_hostname") {
-        return hostname.trim().to_string();
+/// Checks that the AMDuProfPcm path runs something that calls itself uProf;
+/// the binary is only run when it changed since the last successful check
+async fn validate_uprof_binary(expected: &str) -> Result<(), String> {
+    let binary = Path::new(UPROF_PCM);
+    let cached = version_cache::load(binary);
+    let version = match &cached {
+        Some(version) => version.clone(),
+        None => {
+            let output = tokio::process::Command::new(binary).arg("--version").kill_on_drop(true).output();
+            let output = time::timeout(VERSION_TIMEOUT, output)
+                .await
+                .map_err(|_| format!("{} --version did not finish within {}s", UPROF_PCM, VERSION_TIMEOUT.as_secs()))?
+                .map_err(|e| format!("cannot run {}: {}", UPROF_PCM, e))?;
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+    };
+    if !version.contains(expected) {
+        return Err(format!("{} --version does not mention {}: {}", UPROF_PCM, expected, version.trim()));
+    }
+    if cached.is_none() {
+        version_cache::store(binary, &version);
     }
+    Ok(())
+}
 
-    // Попытка прочитать из /etc/host_hostname
-    if let Ok(hostname) = fs::read_to_string("/etc/host_hostname") {
-        return hostname.trim().to_string();
+/// How AMDuProfPcm is run and its reports read
+struct UProfSettings {
+    generation: Option<cpu::ZenGeneration>,
+    output: output::OutputFiles,
+    verify_checksum: bool,
+    parallel_profiles: bool,
+    mmap_output: bool,
+}
+
+async fn collect_metrics(
+    uprof: &UProfSettings,
+    metrics: &Metrics,
+    subprocess: Option<&subprocess::SubprocessThread>,
+    packages: Option<&[u32]>,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    // Taken once, the admin API may toggle groups while AMDuProfPcm runs
+    let groups: Vec<&metrics::MetricGroup> = metrics.enabled_groups().collect();
+    if groups.is_empty() {
+        return Ok(vec![f64::NAN; columns::COUNT]);
+    }
+    if !uprof.parallel_profiles || groups.len() == 1 {
+        let profiles: Vec<&str> = groups.iter().map(|group| group.profiles).collect();
+        let report = run_uprof(uprof, &profiles.join(","), None, subprocess, packages)
+            .await
+            .map_err(|e| e as Box<dyn std::error::Error>)?;
+        let profiled = |column| groups.iter().any(|group| group.columns.contains(&column));
+        return parse_uprof_output(&report, uprof.generation, profiled).ok_or("Failed to parse output".into());
     }
 
-    // Fallback: попытка получить hostname контейнера
-    if let Ok(output) = Command::new("hostname").output() {
-        if output.status.success() {
-            return String::from_utf8_lossy(&output.stdout).trim().to_string();
+    // One AMDuProfPcm per group at the same time, each with its own report
+    let runs = groups
+        .iter()
+        .map(|group| run_uprof(uprof, group.profiles, Some(group.name), subprocess, packages));
+    let reports = try_join_all(runs).await.map_err(|e| e as Box<dyn std::error::Error>)?;
+    let mut values = vec![f64::NAN; columns::COUNT];
+    for (group, report) in groups.iter().zip(reports) {
+        let row = parse_uprof_output(&report, uprof.generation, |column| group.columns.contains(&column))
+            .ok_or_else(|| format!("Failed to parse output of {}", group.name))?;
+        for column in group.columns.clone() {
+            values[column] = row[column];
         }
     }
+    Ok(values)
+}
 
-    "unknown".to_string()
+/// A report read back from AMDuProfPcm
+struct Report {
+    data: output::ReportData,
+    /// Column names from the metadata file uProf may write next to the report
+    columns: Option<Vec<String>>,
 }
 
-impl Metrics {
-    fn new() -> Self {
-        let registry = Registry::new();
-        let nodename = get_host_hostname();
-
-        let ic_fetch_miss_ratio = GaugeVec::new(
-            Opts::new("amd_ic_fetch_miss_ratio", "IC Fetch Miss Ratio"),
-            &["nodename"]
-        ).unwrap();
-        let op_cache_fetch_miss_ratio = GaugeVec::new(
-            Opts::new("amd_op_cache_fetch_miss_ratio", "Op Cache Fetch Miss Ratio"),
-            &["nodename"]
-        ).unwrap();
-        let ic_access_pti = GaugeVec::new(
-            Opts::new("amd_ic_access_pti", "IC Access (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let ic_miss_pti = GaugeVec::new(
-            Opts::new("amd_ic_miss_pti", "IC Miss (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let dc_access_pti = GaugeVec::new(
-            Opts::new("amd_dc_access_pti", "DC Access (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_access_pti = GaugeVec::new(
-            Opts::new("amd_l2_access_pti", "L2 Access (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_access_from_ic_miss_pti = GaugeVec::new(
-            Opts::new("amd_l2_access_from_ic_miss_pti", "L2 Access from IC Miss (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_access_from_dc_miss_pti = GaugeVec::new(
-            Opts::new("amd_l2_access_from_dc_miss_pti", "L2 Access from DC Miss (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_access_from_l2_hwpf_pti = GaugeVec::new(
-            Opts::new("amd_l2_access_from_l2_hwpf_pti", "L2 Access from L2 HWPF (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_miss_pti = GaugeVec::new(
-            Opts::new("amd_l2_miss_pti", "L2 Miss (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_miss_from_ic_miss_pti = GaugeVec::new(
-            Opts::new("amd_l2_miss_from_ic_miss_pti", "L2 Miss from IC Miss (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_miss_from_dc_miss_pti = GaugeVec::new(
-            Opts::new("amd_l2_miss_from_dc_miss_pti", "L2 Miss from DC Miss (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_miss_from_l2_hwpf_pti = GaugeVec::new(
-            Opts::new("amd_l2_miss_from_l2_hwpf_pti", "L2 Miss from L2 HWPF (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_hit_pti = GaugeVec::new(
-            Opts::new("amd_l2_hit_pti", "L2 Hit (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_hit_from_ic_miss_pti = GaugeVec::new(
-            Opts::new("amd_l2_hit_from_ic_miss_pti", "L2 Hit from IC Miss (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_hit_from_dc_miss_pti = GaugeVec::new(
-            Opts::new("amd_l2_hit_from_dc_miss_pti", "L2 Hit from DC Miss (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l2_hit_from_l2_hwpf_pti = GaugeVec::new(
-            Opts::new("amd_l2_hit_from_l2_hwpf_pti", "L2 Hit from L2 HWPF (pti)"),
-            &["nodename"]
-        ).unwrap();
-        let l3_access = GaugeVec::new(
-            Opts::new("amd_l3_access", "L3 Access"),
-            &["nodename"]
-        ).unwrap();
-        let l3_miss = GaugeVec::new(
-            Opts::new("amd_l3_miss", "L3 Miss"),
-            &["nodename"]
-        ).unwrap();
-        let l3_miss_percent = GaugeVec::new(
-            Opts::new("amd_l3_miss_percent", "L3 Miss %"),
-            &["nodename"]
-        ).unwrap();
-        let l3_hit_percent = GaugeVec::new(
-            Opts::new("amd_l3_hit_percent", "L3 Hit %"),
-            &["nodename"]
-        ).unwrap();
-        let ave_l3_miss_latency_ns = GaugeVec::new(
-            Opts::new("amd_ave_l3_miss_latency_ns", "Ave L3 Miss Latency (ns)"),
-            &["nodename"]
-        ).unwrap();
-        let total_mem_bw_gbps = GaugeVec::new(
-            Opts::new("amd_total_mem_bw_gbps", "Total Mem Bw (GB/s)"),
-            &["nodename"]
-        ).unwrap();
-        let local_dram_read_data_bytes_gbps = GaugeVec::new(
-            Opts::new("amd_local_dram_read_data_bytes_gbps", "Local DRAM Read Data Bytes(GB/s)"),
-            &["nodename"]
-        ).unwrap();
-        let local_dram_write_data_bytes_gbps = GaugeVec::new(
-            Opts::new("amd_local_dram_write_data_bytes_gbps", "Local DRAM Write Data Bytes(GB/s)"),
-            &["nodename"]
-        ).unwrap();
-        let remote_dram_read_data_bytes_gbps = GaugeVec::new(
-            Opts::new("amd_remote_dram_read_data_bytes_gbps", "Remote DRAM Read Data Bytes (GB/s)"),
-            &["nodename"]
-        ).unwrap();
-        let remote_dram_write_data_bytes_gbps = GaugeVec::new(
-            Opts::new("amd_remote_dram_write_data_bytes_gbps", "Remote DRAM Write Data Bytes (GB/s)"),
-            &["nodename"]
-        ).unwrap();
-        let total_mem_rdbw_gbps = GaugeVec::new(
-            Opts::new("amd_total_mem_rdbw_gbps", "Total Mem RdBw (GB/s)"),
-            &["nodename"]
-        ).unwrap();
-        let total_mem_wrbw_gbps = GaugeVec::new(
-            Opts::new("amd_total_mem_wrbw_gbps", "Total Mem WrBw (GB/s)"),
-            &["nodename"]
-        ).unwrap();
-
-        registry.register(Box::new(ic_fetch_miss_ratio.clone())).unwrap();
-        registry.register(Box::new(op_cache_fetch_miss_ratio.clone())).unwrap();
-        registry.register(Box::new(ic_access_pti.clone())).unwrap();
-        registry.register(Box::new(ic_miss_pti.clone())).unwrap();
-        registry.register(Box::new(dc_access_pti.clone())).unwrap();
-        registry.register(Box::new(l2_access_pti.clone())).unwrap();
-        registry.register(Box::new(l2_access_from_ic_miss_pti.clone())).unwrap();
-        registry.register(Box::new(l2_access_from_dc_miss_pti.clone())).unwrap();
-        registry.register(Box::new(l2_access_from_l2_hwpf_pti.clone())).unwrap();
-        registry.register(Box::new(l2_miss_pti.clone())).unwrap();
-        registry.register(Box::new(l2_miss_from_ic_miss_pti.clone())).unwrap();
-        registry.register(Box::new(l2_miss_from_dc_miss_pti.clone())).unwrap();
-        registry.register(Box::new(l2_miss_from_l2_hwpf_pti.clone())).unwrap();
-        registry.register(Box::new(l2_hit_pti.clone())).unwrap();
-        registry.register(Box::new(l2_hit_from_ic_miss_pti.clone())).unwrap();
-        registry.register(Box::new(l2_hit_from_dc_miss_pti.clone())).unwrap();
-        registry.register(Box::new(l2_hit_from_l2_hwpf_pti.clone())).unwrap();
-        registry.register(Box::new(l3_access.clone())).unwrap();
-        registry.register(Box::new(l3_miss.clone())).unwrap();
-        registry.register(Box::new(l3_miss_percent.clone())).unwrap();
-        registry.register(Box::new(l3_hit_percent.clone())).unwrap();
-        registry.register(Box::new(ave_l3_miss_latency_ns.clone())).unwrap();
-        registry.register(Box::new(total_mem_bw_gbps.clone())).unwrap();
-        registry.register(Box::new(local_dram_read_data_bytes_gbps.clone())).unwrap();
-        registry.register(Box::new(local_dram_write_data_bytes_gbps.clone())).unwrap();
-        registry.register(Box::new(remote_dram_read_data_bytes_gbps.clone())).unwrap();
-        registry.register(Box::new(remote_dram_write_data_bytes_gbps.clone())).unwrap();
-        registry.register(Box::new(total_mem_rdbw_gbps.clone())).unwrap();
-        registry.register(Box::new(total_mem_wrbw_gbps.clone())).unwrap();
-
-        Self {
-            registry,
-            nodename,
-            ic_fetch_miss_ratio,
-            op_cache_fetch_miss_ratio,
-            ic_access_pti,
-            ic_miss_pti,
-            dc_access_pti,
-            l2_access_pti,
-            l2_access_from_ic_miss_pti,
-            l2_access_from_dc_miss_pti,
-            l2_access_from_l2_hwpf_pti,
-            l2_miss_pti,
-            l2_miss_from_ic_miss_pti,
-            l2_miss_from_dc_miss_pti,
-            l2_miss_from_l2_hwpf_pti,
-            l2_hit_pti,
-            l2_hit_from_ic_miss_pti,
-            l2_hit_from_dc_miss_pti,
-            l2_hit_from_l2_hwpf_pti,
-            l3_access,
-            l3_miss,
-            l3_miss_percent,
-            l3_hit_percent,
-            ave_l3_miss_latency_ns,
-            total_mem_bw_gbps,
-            local_dram_read_data_bytes_gbps,
-            local_dram_write_data_bytes_gbps,
-            remote_dram_read_data_bytes_gbps,
-            remote_dram_write_data_bytes_gbps,
-            total_mem_rdbw_gbps,
-            total_mem_wrbw_gbps,
-        }
-    }
-
-    fn update(&self, values: Vec<f64>) {
-        if values.len() >= 29 {
-            self.ic_fetch_miss_ratio.with_label_values(&[&self.nodename]).set(values[0]);
-            self.op_cache_fetch_miss_ratio.with_label_values(&[&self.nodename]).set(values[1]);
-            self.ic_access_pti.with_label_values(&[&self.nodename]).set(values[2]);
-            self.ic_miss_pti.with_label_values(&[&self.nodename]).set(values[3]);
-            self.dc_access_pti.with_label_values(&[&self.nodename]).set(values[4]);
-            self.l2_access_pti.with_label_values(&[&self.nodename]).set(values[5]);
-            self.l2_access_from_ic_miss_pti.with_label_values(&[&self.nodename]).set(values[6]);
-            self.l2_access_from_dc_miss_pti.with_label_values(&[&self.nodename]).set(values[7]);
-            self.l2_access_from_l2_hwpf_pti.with_label_values(&[&self.nodename]).set(values[8]);
-            self.l2_miss_pti.with_label_values(&[&self.nodename]).set(values[9]);
-            self.l2_miss_from_ic_miss_pti.with_label_values(&[&self.nodename]).set(values[10]);
-            self.l2_miss_from_dc_miss_pti.with_label_values(&[&self.nodename]).set(values[11]);
-            self.l2_miss_from_l2_hwpf_pti.with_label_values(&[&self.nodename]).set(values[12]);
-            self.l2_hit_pti.with_label_values(&[&self.nodename]).set(values[13]);
-            self.l2_hit_from_ic_miss_pti.with_label_values(&[&self.nodename]).set(values[14]);
-            self.l2_hit_from_dc_miss_pti.with_label_values(&[&self.nodename]).set(values[15]);
-            self.l2_hit_from_l2_hwpf_pti.with_label_values(&[&self.nodename]).set(values[16]);
-            self.l3_access.with_label_values(&[&self.nodename]).set(values[17]);
-            self.l3_miss.with_label_values(&[&self.nodename]).set(values[18]);
-            self.l3_miss_percent.with_label_values(&[&self.nodename]).set(values[19]);
-            self.l3_hit_percent.with_label_values(&[&self.nodename]).set(values[20]);
-            self.ave_l3_miss_latency_ns.with_label_values(&[&self.nodename]).set(values[21]);
-            self.total_mem_bw_gbps.with_label_values(&[&self.nodename]).set(values[22]);
-            self.local_dram_read_data_bytes_gbps.with_label_values(&[&self.nodename]).set(values[23]);
-            self.local_dram_write_data_bytes_gbps.with_label_values(&[&self.nodename]).set(values[24]);
-            self.remote_dram_read_data_bytes_gbps.with_label_values(&[&self.nodename]).set(values[25]);
-            self.remote_dram_write_data_bytes_gbps.with_label_values(&[&self.nodename]).set(values[26]);
-            self.total_mem_rdbw_gbps.with_label_values(&[&self.nodename]).set(values[27]);
-            self.total_mem_wrbw_gbps.with_label_values(&[&self.nodename]).set(values[28]);
+/// Runs AMDuProfPcm once and returns its report; `name` tells apart the
+/// reports of runs that overlap
+async fn run_uprof(
+    uprof: &UProfSettings,
+    profiles: &str,
+    name: Option<&str>,
+    subprocess: Option<&subprocess::SubprocessThread>,
+    packages: Option<&[u32]>,
+) -> Result<Report, Box<dyn std::error::Error + Send + Sync>> {
+    let mut command = Command::new(UPROF_PCM);
+    command.args(["-m", profiles]);
+    match packages {
+        Some(packages) => {
+            let packages: Vec<String> = packages.iter().map(ToString::to_string).collect();
+            command.args(["-c", &format!("package={}", packages.join(","))]);
+        }
+        None => {
+            command.arg("-a");
+        }
+    }
+    // Concurrent runs restricted to some sockets need their own report files
+    let mut suffix: Vec<String> = packages.unwrap_or_default().iter().map(ToString::to_string).collect();
+    suffix.extend(name.map(str::to_string));
+    let output_path = uprof.output.path(&suffix);
+    command.args(["-d", "1", "-r", "-o"]);
+    command.arg(&output_path);
+    command.arg("--msr");
+    let output = subprocess::output(command, subprocess).await?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stderr.to_lowercase().contains("license") || stdout.to_lowercase().contains("license") {
+            let message = if stderr.trim().is_empty() { stdout } else { stderr };
+            return Err(error::CollectionError::LicenseNotAccepted(message.trim().to_string()).into());
         }
+        return Err(format!("AMDuProfPcm failed: {}", stderr).into());
     }
+
+    let data = output::ReportData::load(&output_path, uprof.mmap_output).await?;
+    let columns = output::read_metadata(&output_path).await;
+    let checksum = match uprof.verify_checksum {
+        true => output::verify_checksum(&output_path, data.as_bytes()).await,
+        false => Ok(()),
+    };
+    uprof.output.clean_up(&output_path).await;
+    checksum?;
+    Ok(Report { data, columns })
 }
 
-fn parse_uprof_output(content: &str) -> Option<Vec<f64>> {
-    let lines: Vec<&str> = content.lines().collect();
-    for line in lines.iter().rev() {
-        if line.contains(',') && !line.contains("System") && !line.contains("METRICS") {
-            let parts: Vec<&str> = line.split(',').collect();
-            if parts.len() > 28 {
-                let mut values = Vec::new();
-                for i in 0..29 {
-                    if let Some(val) = parts.get(i) {
-                        if let Ok(num) = val.trim().parse::<f64>() {
-                            values.push(num);
-                        } else {
-                            values.push(0.0);
-                        }
+/// AMDuProfPcm restricted to some sockets, run from a thread pinned to them
+struct Worker {
+    packages: Vec<u32>,
+    thread: subprocess::SubprocessThread,
+}
+
+/// Spreads sockets round-robin over `count` workers
+fn spawn_workers(count: usize, rt_priority: Option<i32>) -> Vec<Worker> {
+    let sockets: Vec<(u32, Vec<usize>)> = cpu::package_cpus().into_iter().collect();
+    let count = count.min(sockets.len());
+    (0..count)
+        .map(|index| {
+            let assigned: Vec<&(u32, Vec<usize>)> = sockets.iter().skip(index).step_by(count).collect();
+            let packages: Vec<u32> = assigned.iter().map(|(package, _)| *package).collect();
+            let cpus: Vec<usize> = assigned.iter().flat_map(|(_, cpus)| cpus.iter().copied()).collect();
+            Worker {
+                packages,
+                thread: subprocess::SubprocessThread::spawn(&cpus, rt_priority),
+            }
+        })
+        .collect()
+}
+
+/// Runs every worker concurrently and merges their rows; fails as a whole
+/// if any worker fails so that only complete rows reach the gauges
+async fn collect_parallel(
+    uprof: &Arc<UProfSettings>,
+    metrics: &Arc<Metrics>,
+    workers: &Arc<Vec<Worker>>,
+) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    let mut tasks = JoinSet::new();
+    for index in 0..workers.len() {
+        let uprof = uprof.clone();
+        let metrics = metrics.clone();
+        let workers = workers.clone();
+        tasks.spawn(async move {
+            let worker = &workers[index];
+            collect_metrics(&uprof, &metrics, Some(&worker.thread), Some(&worker.packages))
+                .await
+                .map_err(|e| -> Box<dyn std::error::Error + Send + Sync> {
+                    // Keep specific failures recognisable by the caller
+                    match e.downcast::<error::CollectionError>() {
+                        Ok(e) => e,
+                        Err(e) => format!("sockets {:?}: {}", worker.packages, e).into(),
                     }
+                })
+        });
+    }
+
+    let mut rows = Vec::with_capacity(workers.len());
+    while let Some(row) = tasks.join_next().await {
+        rows.push(row?.map_err(|e| e as Box<dyn std::error::Error>)?);
+    }
+    Ok(columns::merge(&rows))
+}
+
+/// Appended when families had to be dropped to honour `--max-response-bytes`
+const TRUNCATION_SENTINEL: &str = "# HELP amd_uprof_truncated 1\n";
+
+/// Encodes whole metric families until `max_bytes` would be exceeded;
+/// returns the buffer and whether anything was left out
+fn encode_limited<E: Encoder>(encoder: &E, families: &[MetricFamily], max_bytes: usize) -> (Vec<u8>, bool) {
+    // Keep room for the sentinel so the response never exceeds the limit
+    let limit = max_bytes.saturating_sub(TRUNCATION_SENTINEL.len());
+    let mut buffer = vec![];
+    for family in families {
+        let mut encoded = vec![];
+        encoder.encode(std::slice::from_ref(family), &mut encoded).unwrap();
+        if buffer.len() + encoded.len() > limit {
+            buffer.extend_from_slice(TRUNCATION_SENTINEL.as_bytes());
+            return (buffer, true);
+        }
+        buffer.extend_from_slice(&encoded);
+    }
+    (buffer, false)
+}
+
+/// Gauges that get the trace ID of `--tracing-header` as an exemplar
+const EXEMPLAR_FAMILIES: [&str; 2] = ["amd_l3_miss_percent", "amd_total_mem_bw_gbps"];
+
+/// Exposition format negotiated from the Accept header
+#[derive(Clone)]
+enum Format {
+    Text,
+    /// With the trace ID of `--tracing-header`, if the request had one
+    OpenMetrics { trace_id: Option<String> },
+}
+
+/// How a scrape is rendered, from the request headers
+#[derive(Clone)]
+struct Scrape {
+    format: Format,
+    /// Restricts the output to the tenant's nodenames
+    tenant: Option<Arc<tenant::Tenant>>,
+}
+
+/// Interval between collections, also advertised as the scrape cache lifetime
+const COLLECTION_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Scrape encoded right after the last successful collection
+struct CachedResult {
+    buffer: Bytes,
+    collected_at: Instant,
+}
+
+type Cache = Arc<RwLock<Option<CachedResult>>>;
+
+/// Requests for an immediate collection, answered once it has finished
+type LiveRequest = oneshot::Sender<Result<(), String>>;
+
+struct LiveCollection {
+    requests: mpsc::Sender<LiveRequest>,
+    timeout: Duration,
+    max_staleness: Duration,
+}
+
+/// State shared by the collection loop and the HTTP handlers
+struct AppState {
+    registry: Registry,
+    self_registry: self_metrics::SelfRegistry,
+    metrics: Arc<Metrics>,
+    node_labels: Arc<k8s::NodeLabels>,
+    phase: Arc<hooks::Phase>,
+    max_response_bytes: usize,
+    truncated: IntCounter,
+    readiness: Arc<health::Readiness>,
+    cache: Cache,
+    live: LiveCollection,
+    default_mode: cli::ScrapeMode,
+    last_collection: Arc<Mutex<Option<Instant>>>,
+    collection_errors: Arc<AtomicU64>,
+    trusted_proxies: Vec<proxy::Cidr>,
+    access_log: Option<access_log::AccessLog>,
+    http_metrics: self_metrics::HttpMetrics,
+    basic_auth: Option<web_config::BasicAuth>,
+    /// uProf values of the previous and the latest successful collection
+    snapshots: Mutex<(snapshot::MetricsSnapshot, snapshot::MetricsSnapshot)>,
+    diff_threshold_percent: f64,
+    tracing_header: Option<String>,
+    tenants: Option<tenant::Tenants>,
+    federation: Option<Arc<federation::Federation>>,
+    enable_admin_api: bool,
+}
+
+impl AppState {
+    fn gather(&self) -> Vec<MetricFamily> {
+        let mut metric_families = match &self.federation {
+            Some(federation) => federation.gather(),
+            None => {
+                let mut metric_families = self.registry.gather();
+                metric_families.extend(self.metrics.gather());
+                metric_families
+            }
+        };
+        self.node_labels.apply(&mut metric_families);
+        self.phase.apply(&mut metric_families);
+        metric_families.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+        metric_families
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.encode_with(&TextEncoder::new(), &self.gather(), self.max_response_bytes)
+    }
+
+    /// Renders a scrape that the cached text cannot answer; OpenMetrics is
+    /// timestamped with the last successful collection
+    fn render(&self, scrape: &Scrape) -> Response<Body> {
+        let mut families = self.gather();
+        if let Some(tenant) = &scrape.tenant {
+            tenant.filter(&mut families);
+        }
+        let age = self.cached().map_or(Duration::ZERO, |(_, age)| age);
+        match &scrape.format {
+            Format::Text => {
+                let buffer = self.encode_with(&TextEncoder::new(), &families, self.max_response_bytes);
+                metrics_response(Bytes::from(buffer), age)
+            }
+            Format::OpenMetrics { trace_id } => {
+                let collected_at = self.snapshots.lock().unwrap().1.collected_at;
+                let mut encoder = openmetrics::OpenMetricsEncoder::new(collected_at);
+                if let Some(trace_id) = trace_id {
+                    encoder = encoder.exemplar(openmetrics::Exemplar { trace_id, families: &EXEMPLAR_FAMILIES });
                 }
-                return Some(values);
+                let max_bytes = self.max_response_bytes.saturating_sub(openmetrics::EOF.len());
+                let mut buffer = self.encode_with(&encoder, &families, max_bytes);
+                buffer.extend_from_slice(openmetrics::EOF.as_bytes());
+                encoded_response(openmetrics::FORMAT_TYPE, Bytes::from(buffer), age)
             }
         }
     }
-    None
-}
 
-async fn collect_metrics() -> Result<Vec<f64>, Box<dyn std::error::Error>> {
-    let output_path = "/var/uprof/uprof_metrics.csv";
-    let output = Command::new("/opt/AMDuProf_Linux_x64_5.1.701/bin/AMDuProfPcm")
-        .args(&[
-            "-m", "memory,l1,l2,l3",
-            "-a",
-            "-d", "1",
-            "-r",
-            "-o", output_path,
-            "--msr"
-        ])
-        .output()?;
+    fn encode_with<E: Encoder>(&self, encoder: &E, families: &[MetricFamily], max_bytes: usize) -> Vec<u8> {
+        let (buffer, was_truncated) = encode_limited(encoder, families, max_bytes);
+        if was_truncated {
+            self.truncated.inc();
+            eprintln!("Response truncated to {} bytes", buffer.len());
+        }
+        buffer
+    }
+
+    /// The cached text scrape when it fits the request
+    fn scrape_response(&self, scrape: &Scrape, buffer: Bytes, age: Duration) -> Response<Body> {
+        match (&scrape.format, &scrape.tenant) {
+            (Format::Text, None) => metrics_response(buffer, age),
+            _ => self.render(scrape),
+        }
+    }
 
-    if !output.status.success() {
-        return Err(format!("AMDuProfPcm failed: {}",
-                           String::from_utf8_lossy(&output.stderr)).into());
+    fn cached(&self) -> Option<(Bytes, Duration)> {
+        let cache = self.cache.read().unwrap();
+        let cached = cache.as_ref()?;
+        Some((cached.buffer.clone(), cached.collected_at.elapsed()))
     }
 
-    let content = fs::read_to_string(output_path)?;
-    let _ = fs::remove_file(output_path);
+    /// Bookkeeping after every collection attempt
+    fn record_collection(&self, succeeded: bool) {
+        if !succeeded {
+            self.collection_errors.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let now = Instant::now();
+        *self.last_collection.lock().unwrap() = Some(now);
+        let mut snapshots = self.snapshots.lock().unwrap();
+        snapshots.0 = std::mem::replace(&mut snapshots.1, self.metrics.snapshot());
+        drop(snapshots);
+        *self.cache.write().unwrap() = Some(CachedResult {
+            buffer: Bytes::from(self.encode()),
+            collected_at: now,
+        });
+    }
+}
 
-    parse_uprof_output(&content).ok_or("Failed to parse output".into())
+/// uProf values that changed between the last two successful collections
+fn diff_handler(state: &AppState) -> Response<Body> {
+    let (text, unchanged) = {
+        let snapshots = state.snapshots.lock().unwrap();
+        snapshots.1.diff(&snapshots.0, state.diff_threshold_percent)
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", TextEncoder::new().format_type())
+        .header("X-Unchanged-Count", unchanged)
+        .body(Body::from(text))
+        .unwrap()
 }
 
-async fn metrics_handler(
-    _req: Request<Body>,
-    registry: Registry,
-) -> Result<Response<Body>, hyper::Error> {
-    let encoder = TextEncoder::new();
-    let metric_families = registry.gather();
-    let mut buffer = vec![];
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+fn metrics_response(buffer: Bytes, age: Duration) -> Response<Body> {
+    encoded_response(TextEncoder::new().format_type(), buffer, age)
+}
 
-    Ok(Response::builder()
+fn encoded_response(format_type: &str, buffer: Bytes, age: Duration) -> Response<Body> {
+    Response::builder()
         .status(StatusCode::OK)
-        .header("Content-Type", encoder.format_type())
+        .header("Content-Type", format_type)
+        .header("Vary", "Accept")
+        .header("Cache-Control", format!("max-age={}", COLLECTION_INTERVAL.as_secs()))
+        .header("Age", age.as_secs())
         .body(Body::from(buffer))
-        .unwrap())
+        .unwrap()
+}
+
+/// Serves the cached scrape so that a slow collection never delays
+/// Prometheus; renders one on the spot until the first collection is done,
+/// off the runtime threads. The encoding time counts towards the request
+/// duration histogram like the rest of the handler.
+async fn metrics_handler(state: &Arc<AppState>, scrape: &Scrape) -> Result<Response<Body>, hyper::Error> {
+    if let Some((buffer, age)) = state.cached() {
+        return Ok(state.scrape_response(scrape, buffer, age));
+    }
+    let (encoding, scrape) = (Arc::clone(state), scrape.clone());
+    Ok(tokio::task::spawn_blocking(move || encoding.render(&scrape))
+        .await
+        .expect("encoding panicked"))
+}
+
+/// `/metrics/cached`: the last cached scrape only, 503 before the first collection
+async fn cached_handler(state: &AppState, scrape: &Scrape) -> Result<Response<Body>, hyper::Error> {
+    Ok(match state.cached() {
+        Some((buffer, age)) => state.scrape_response(scrape, buffer, age),
+        None => admin::text_response(StatusCode::SERVICE_UNAVAILABLE, "no collection has completed yet"),
+    })
+}
+
+/// `/metrics/live`: runs a collection now and serves its result, waiting at
+/// most `--collection-timeout-secs`. If it fails, a cached result no older
+/// than `--max-staleness-secs` is served instead; never anything older.
+async fn live_handler(state: &AppState, scrape: &Scrape) -> Result<Response<Body>, hyper::Error> {
+    let (reply, done) = oneshot::channel();
+    let (status, message) = if state.live.requests.send(reply).await.is_err() {
+        (StatusCode::SERVICE_UNAVAILABLE, "collector is not running".to_string())
+    } else {
+        match time::timeout(state.live.timeout, done).await {
+            Err(_) => (StatusCode::GATEWAY_TIMEOUT, "collection timed out".to_string()),
+            Ok(Err(_)) => (StatusCode::SERVICE_UNAVAILABLE, "collector is not running".to_string()),
+            Ok(Ok(Err(e))) => (StatusCode::SERVICE_UNAVAILABLE, format!("collection failed: {}", e)),
+            Ok(Ok(Ok(()))) => {
+                let (buffer, age) = state.cached().expect("cache is filled by a successful collection");
+                return Ok(state.scrape_response(scrape, buffer, age));
+            }
+        }
+    };
+    Ok(match state.cached().filter(|(_, age)| *age <= state.live.max_staleness) {
+        Some((buffer, age)) => state.scrape_response(scrape, buffer, age),
+        None => admin::text_response(status, &message),
+    })
+}
+
+const INDEX: &str = "AMD uProf Prometheus exporter\n\n\
+    Hardware counters collected with AMDuProfPcm are served at /metrics";
+
+async fn route(
+    req: Request<Body>,
+    state: Arc<AppState>,
+    tenant: Option<Arc<tenant::Tenant>>,
+) -> Result<Response<Body>, hyper::Error> {
+    let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok());
+    let format = if openmetrics::accepted(header("Accept")) {
+        let trace_id = state.tracing_header.as_deref().and_then(header).map(str::to_string);
+        Format::OpenMetrics { trace_id }
+    } else {
+        Format::Text
+    };
+    // Tenants only get the scrapes their filter applies to
+    let scrape_paths = ["/metrics", "/metrics/live", "/metrics/cached", "/metrics/stale", "/", "/robots.txt"];
+    if tenant.is_some() && !scrape_paths.contains(&req.uri().path()) {
+        return Ok(admin::text_response(StatusCode::FORBIDDEN, "not available to tenants"));
+    }
+    let scrape = Scrape { format, tenant };
+    match req.uri().path() {
+        "/admin/groups" => {
+            admin::groups_handler(req, Arc::clone(&state.metrics), state.enable_admin_api).await
+        },
+        "/readyz" => {
+            let last_collection = *state.last_collection.lock().unwrap();
+            let errors = state.collection_errors.load(Ordering::Relaxed);
+            health::readyz_handler(&state.readiness, last_collection, errors).await
+        }
+        "/metrics/live" => live_handler(&state, &scrape).await,
+        "/metrics/cached" => cached_handler(&state, &scrape).await,
+        "/metrics/stale" => metrics_handler(&state, &scrape).await,
+        "/metrics/self" => Ok(metrics_response(Bytes::from(state.self_registry.encode()), Duration::ZERO)),
+        "/metrics" => match state.default_mode {
+            cli::ScrapeMode::Cached => metrics_handler(&state, &scrape).await,
+            cli::ScrapeMode::Live => live_handler(&state, &scrape).await,
+        },
+        "/metrics/diff" => Ok(diff_handler(&state)),
+        "/snapshot" => Ok(admin::json_response(StatusCode::OK, &state.metrics.snapshot())),
+        "/robots.txt" => Ok(admin::text_response(StatusCode::OK, "User-agent: *\nDisallow: /")),
+        "/" => Ok(admin::text_response(StatusCode::OK, INDEX)),
+        // Crawlers probing random paths must not trigger collections
+        _ => Ok(admin::text_response(StatusCode::NOT_FOUND, "not found, metrics are at /metrics")),
+    }
+}
+
+/// Nothing served here is meant to be rendered by a browser
+const SECURITY_HEADERS: [(&str, &str); 3] = [
+    ("X-Content-Type-Options", "nosniff"),
+    ("X-Frame-Options", "DENY"),
+    ("Content-Security-Policy", "default-src 'none'"),
+];
+
+/// Tags the response with the request's `X-Request-Id`, or a fresh one,
+/// adds the security headers and logs the exchange under that id
+async fn handle(
+    req: Request<Body>,
+    state: Arc<AppState>,
+    client: SocketAddr,
+) -> Result<Response<Body>, hyper::Error> {
+    let request_id = req
+        .headers()
+        .get("X-Request-Id")
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_str(&Uuid::new_v4().to_string()).unwrap());
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let target = req.uri().to_string();
+    let version = format!("{:?}", req.version());
+    let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+    let (referer, user_agent) = (header("Referer"), header("User-Agent"));
+    let client = proxy::client_ip(req.headers(), client.ip(), &state.trusted_proxies);
+
+    let started = Instant::now();
+    // A tenant's bearer token stands in for basic auth
+    let identity = match &state.tenants {
+        Some(tenants) => tenants.identify(req.headers()),
+        None => tenant::Identity::Anonymous,
+    };
+    let (authorized, tenant) = match identity {
+        tenant::Identity::Tenant(tenant) => (true, Some(tenant)),
+        tenant::Identity::Unknown => (false, None),
+        tenant::Identity::Anonymous => match &state.basic_auth {
+            Some(basic_auth) => (basic_auth.check(req.headers()).await, None),
+            None => (true, None),
+        },
+    };
+    let tenant_name = tenant.as_ref().map(|tenant| tenant.name.clone());
+    let mut response = if authorized {
+        route(req, Arc::clone(&state), tenant).await?
+    } else {
+        let mut response = admin::text_response(StatusCode::UNAUTHORIZED, "unauthorized");
+        response
+            .headers_mut()
+            .insert("WWW-Authenticate", HeaderValue::from_static("Basic realm=\"uprof-exporter\""));
+        response
+    };
+    response.headers_mut().insert("X-Request-Id", request_id.clone());
+    for (name, value) in SECURITY_HEADERS {
+        response.headers_mut().insert(name, HeaderValue::from_static(value));
+    }
+    let size = response.body().size_hint().exact();
+    state.http_metrics.record(&path, response.status().as_u16(), started.elapsed(), size);
+    if let Some(access_log) = &state.access_log {
+        access_log.record(&access_log::Entry {
+            client,
+            method: method.as_str(),
+            target: &target,
+            version: &version,
+            status: response.status().as_u16(),
+            size,
+            referer: referer.as_deref(),
+            user_agent: user_agent.as_deref(),
+        });
+    }
+    tracing::debug!(
+        request_id = %String::from_utf8_lossy(request_id.as_bytes()),
+        %client,
+        tenant = tenant_name,
+        %method,
+        path,
+        status = response.status().as_u16(),
+        size,
+        "request"
+    );
+    Ok(response)
+}
+
+/// Accepts connections forever, each served over HTTP/1.1 or, for clients
+/// that start with its preface, HTTP/2; inside TLS when it is configured
+async fn serve(listener: TcpListener, state: Arc<AppState>, tls: Option<TlsAcceptor>) {
+    loop {
+        let (stream, client) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                // Usually out of file descriptors, give the open ones time to close
+                eprintln!("Failed to accept connection: {}", e);
+                time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let state = Arc::clone(&state);
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req| handle(req, Arc::clone(&state), client));
+            let served = match tls {
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => Http::new().serve_connection(stream, service).await,
+                    Err(e) => {
+                        tracing::debug!(%client, "TLS handshake failed: {}", e);
+                        return;
+                    }
+                },
+                None => Http::new().serve_connection(stream, service).await,
+            };
+            if let Err(e) = served {
+                tracing::debug!(%client, "connection error: {}", e);
+            }
+        });
+    }
+}
+
+enum Source {
+    UProf(Arc<UProfSettings>),
+    UProfWorkers(Arc<UProfSettings>, Arc<Vec<Worker>>),
+    Msr(msr::MsrSampler),
+    Perf(perf::PerfSampler),
+    IntelPcm,
+    Federation(Arc<federation::Federation>),
+}
+
+impl Source {
+    fn select(args: &cli::Args, generation: Option<cpu::ZenGeneration>, rt_priority: Option<i32>) -> Self {
+        let uprof = Arc::new(UProfSettings {
+            generation,
+            output: output::OutputFiles::new(
+                args.output_path_template.clone(),
+                Duration::from_secs(args.output_max_age_secs),
+                args.archive_dir.clone().map(|dir| output::Archive { dir, count: args.archive_count }),
+            ),
+            verify_checksum: args.verify_checksum,
+            parallel_profiles: args.parallel_profiles,
+            mmap_output: args.mmap_output,
+        });
+        if args.federation_mode {
+            let federation = federation::Federation::new(&args.upstream_urls).unwrap_or_else(|e| {
+                eprintln!("Invalid federation settings: {}", e);
+                std::process::exit(1);
+            });
+            println!("Federating {} upstream exporters", args.upstream_urls.len());
+            return Source::Federation(Arc::new(federation));
+        }
+        if args.intel_fallback && cpu::is_intel() {
+            println!("Intel CPU detected, using Intel PCM");
+            return Source::IntelPcm;
+        }
+        if Path::new(UPROF_PCM).exists() {
+            if args.workers > 1 {
+                let workers = spawn_workers(args.workers, rt_priority);
+                if workers.len() > 1 {
+                    for worker in &workers {
+                        println!("Collection worker for sockets {:?}", worker.packages);
+                    }
+                    return Source::UProfWorkers(uprof, Arc::new(workers));
+                }
+                eprintln!("Warning: a single socket found, ignoring --workers");
+            }
+            return Source::UProf(uprof);
+        }
+        if args.use_perf {
+            match perf::PerfSampler::new() {
+                Ok(sampler) => {
+                    println!("AMDuProfPcm not found, using perf_event_open");
+                    return Source::Perf(sampler);
+                }
+                Err(e) => eprintln!("perf fallback unavailable: {}", e),
+            }
+        }
+        if args.use_msr {
+            match msr::MsrSampler::new() {
+                Ok(sampler) => {
+                    println!("AMDuProfPcm not found, reading MSRs directly");
+                    return Source::Msr(sampler);
+                }
+                Err(e) => eprintln!("MSR fallback unavailable: {}", e),
+            }
+        }
+        Source::UProf(uprof)
+    }
+
+    async fn collect(
+        &mut self,
+        metrics: &Arc<Metrics>,
+        subprocess: Option<&subprocess::SubprocessThread>,
+    ) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+        match self {
+            Source::UProf(uprof) => collect_metrics(uprof, metrics, subprocess, None).await,
+            Source::UProfWorkers(uprof, workers) => collect_parallel(uprof, metrics, workers).await,
+            Source::Msr(sampler) => sampler.sample(),
+            Source::Perf(sampler) => sampler.sample(),
+            Source::IntelPcm => intel::collect_metrics(subprocess).await,
+            // Nothing is collected locally
+            Source::Federation(federation) => {
+                federation.scrape().await?;
+                Ok(vec![f64::NAN; columns::COUNT])
+            }
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let metrics = Metrics::new();
+    let args = cli::Args::parse();
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+    let config = match &args.config {
+        Some(path) => config::Config::load(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load config {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => config::Config::default(),
+    };
+    let web_config = match &args.web_config_file {
+        Some(path) => web_config::WebConfig::load(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load web config {}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        None => web_config::WebConfig::default(),
+    };
+    let tenants = args.tenant_config.as_ref().map(|path| {
+        tenant::Tenants::load(path).unwrap_or_else(|e| {
+            eprintln!("Failed to load tenant config {}: {}", path.display(), e);
+            std::process::exit(1);
+        })
+    });
+    let extra_labels: Vec<&str> = args.extra_labels.iter().map(|(name, _)| name.as_str()).collect();
+    if let Err(e) = k8s::validate_node_labels(&args.k8s_node_labels, &extra_labels) {
+        eprintln!("Invalid --k8s-node-labels: {}", e);
+        std::process::exit(1);
+    }
+    let generation = cpu::ZenGeneration::detect();
+    match generation {
+        Some(generation) => println!("Detected {:?}", generation),
+        None => println!("Zen generation not detected, leaving out generation-specific metrics"),
+    }
+    let schedule = args.schedule.as_deref().map(|expression| {
+        schedule::CollectionSchedule::parse(expression).unwrap_or_else(|e| {
+            eprintln!("Invalid --schedule {:?}: {}", expression, e);
+            std::process::exit(1);
+        })
+    });
+    let affinity = &args.collector_cpu_affinity;
+    if !affinity.is_empty() {
+        let cpus: Vec<String> = affinity.iter().map(ToString::to_string).collect();
+        println!("Pinning collector thread to CPUs {}", cpus.join(","));
+    }
+    let rt_priority = args.rt_priority.filter(|_| {
+        let capable = subprocess::has_cap_sys_nice();
+        if !capable {
+            eprintln!("Warning: CAP_SYS_NICE is missing, ignoring --rt-priority");
+        }
+        capable
+    });
+    let mut source = Source::select(&args, generation, rt_priority);
+    if matches!(source, Source::UProf(_) | Source::UProfWorkers(..)) && !args.skip_binary_validation {
+        if let Err(e) = validate_uprof_binary(&args.binary_version_match).await {
+            eprintln!("Refusing to run AMDuProfPcm: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let subprocess = (args.subprocess_thread || !affinity.is_empty() || rt_priority.is_some())
+        .then(|| subprocess::SubprocessThread::spawn(affinity, rt_priority));
+
+    let hostname = args.nodename.clone().unwrap_or_else(|| get_host_hostname(&args.nodename_source));
+    let nodename = label::sanitize_label_value(&hostname);
+    let registrar = registration::Registrar::new(metrics::new_registry(&args));
+    let metrics = Metrics::try_new(&args, &config, generation, &nodename, &registrar)
+        .unwrap_or_else(|errors| registration::fail(&errors));
+    let metrics = Arc::new(metrics);
     println!("Using nodename: {}", metrics.nodename);
+    let pod_labels = k8s::downward_api_labels();
+    if !pod_labels.is_empty() {
+        println!("Adding Kubernetes labels: {:?}", pod_labels);
+    }
+    let groups: Vec<&str> = metrics.enabled_groups().map(|group| group.name).collect();
+    println!("Enabled metric groups: {}", groups.join(","));
 
-    let registry = metrics.registry.clone();
-    let metrics_clone = std::sync::Arc::new(metrics);
-    let collector_metrics = metrics_clone.clone();
-
-    tokio::spawn(async move {
-        let mut interval = time::interval(Duration::from_secs(2));
-        loop {
-            interval.tick().await;
-            match collect_metrics().await {
-                Ok(values) => {
-                    collector_metrics.update(values);
-                }
-                Err(e) => {
-                    eprintln!("Error collecting metrics: {}", e);
+    let mut host_collectors = if args.federation_mode {
+        Vec::new()
+    } else {
+        collectors::default_collectors(&args, &config, &registrar, &metrics.nodename)
+    };
+    let max_mem_bw_gbps = args.max_mem_bw_gbps.or_else(|| match dmi::memory_devices() {
+        Ok(devices) => dmi::max_mem_bw_gbps(&devices),
+        Err(e) => {
+            eprintln!("Warning: cannot read the DIMM configuration from SMBIOS: {}", e);
+            None
+        }
+    });
+    let mut mem_bw_utilization = match max_mem_bw_gbps {
+        Some(max) => {
+            println!("Peak memory bandwidth: {} GB/s", max);
+            Some(utilization::MemBwUtilization::new(&registrar, &metrics.nodename, max))
+        }
+        None => {
+            eprintln!("Warning: peak memory bandwidth unknown, pass --max-mem-bw-gbps to export its utilization");
+            None
+        }
+    };
+    let mut health_config = config.health_score;
+    health_config.max_mem_bw_gbps = health_config.max_mem_bw_gbps.or(max_mem_bw_gbps);
+    let health_score = health_score::HealthScore::new(&registrar, &metrics.nodename, health_config);
+    let self_registrar = registration::Registrar::new(metrics::new_registry(&args));
+    let collection_metrics = self_metrics::CollectionMetrics::new(&self_registrar, &metrics.nodename);
+    self_metrics::register_process_info(&self_registrar);
+    let http_metrics = self_metrics::HttpMetrics::new(&self_registrar);
+    tokio::spawn(self_metrics::SelfMetrics::new(&self_registrar, &metrics.nodename).run());
+    let deadlock_detector = self_metrics::DeadlockDetector::new(&self_registrar, &metrics.nodename);
+    let mut context_switches = self_metrics::ContextSwitches::new(&self_registrar, &metrics.nodename);
+    let node_labels = k8s::NodeLabels::new(args.k8s_node_labels.clone());
+    let node = std::env::var("NODE_NAME").unwrap_or_else(|_| metrics.nodename.clone());
+    tokio::spawn(node_labels.clone().run(node));
+    let truncated = IntCounter::new(
+        "amd_uprof_response_truncated_total",
+        "Scrapes cut short by --max-response-bytes"
+    ).unwrap();
+    self_registrar.register(&truncated);
+    let federation = match &source {
+        Source::Federation(federation) => {
+            self_registrar.register(federation.up());
+            Some(Arc::clone(federation))
+        }
+        _ => None,
+    };
+    // Every collector's failures are reported together
+    let (registry, self_registry) = match (registrar.finish(), self_registrar.finish()) {
+        (Ok(registry), Ok(self_registry)) => (registry, self_registry),
+        (registry, self_registry) => {
+            let errors: Vec<_> = registry.err().into_iter().chain(self_registry.err()).flatten().collect();
+            registration::fail(&errors)
+        }
+    };
+    let (live_requests, mut pending_live) = mpsc::channel::<LiveRequest>(16);
+    let access_log = match &args.access_log {
+        Some(target) => Some(access_log::AccessLog::open(target).await.unwrap_or_else(|e| {
+            eprintln!("Failed to open access log {}: {}", target, e);
+            std::process::exit(1);
+        })),
+        None => None,
+    };
+    let state = Arc::new(AppState {
+        registry,
+        self_registry: self_metrics::SelfRegistry::new(self_registry),
+        metrics: Arc::clone(&metrics),
+        node_labels,
+        phase: Arc::new(hooks::Phase::default()),
+        max_response_bytes: args.max_response_bytes,
+        truncated,
+        readiness: Arc::new(health::Readiness::default()),
+        cache: Arc::new(RwLock::new(None)),
+        live: LiveCollection {
+            requests: live_requests,
+            timeout: Duration::from_secs(args.collection_timeout_secs),
+            max_staleness: Duration::from_secs(args.max_staleness_secs),
+        },
+        default_mode: args.default_mode,
+        last_collection: Arc::new(Mutex::new(None)),
+        collection_errors: Arc::new(AtomicU64::new(0)),
+        trusted_proxies: args.trusted_proxy_cidrs.clone(),
+        access_log,
+        http_metrics,
+        basic_auth: web_config.basic_auth(),
+        snapshots: Mutex::default(),
+        diff_threshold_percent: args.diff_threshold_percent,
+        tracing_header: args.tracing_header.clone(),
+        tenants,
+        federation,
+        enable_admin_api: args.enable_admin_api,
+    });
+    let pre_collect_hook = args.pre_collect_hook.clone().map(|path| {
+        hooks::PreCollectHook::new(path, Duration::from_millis(args.pre_collect_hook_timeout_ms))
+    });
+
+    if let Some(path) = &args.restore_snapshot {
+        match snapshot::MetricsSnapshot::read(path) {
+            Ok(snapshot) if snapshot.nodename != state.metrics.nodename => {
+                eprintln!("Warning: snapshot {} is from node {}, not restoring it", path.display(), snapshot.nodename);
+            }
+            Ok(snapshot) => {
+                let restored = state.metrics.restore(&snapshot);
+                collection_metrics.record_restore(snapshot.collected_at);
+                println!("Restored {} values from snapshot {}", restored, path.display());
+            }
+            Err(e) => eprintln!("Warning: cannot restore snapshot {}: {}", path.display(), e),
+        }
+    }
+
+    // A wrong binary path or a missing license should stop the exporter
+    // rather than leave it serving empty metrics
+    if !args.skip_startup_test && !args.federation_mode {
+        let result = source.collect(&state.metrics, subprocess.as_ref()).await;
+        collection_metrics.record(result.is_ok());
+        match result {
+            Ok(values) => {
+                state.metrics.update(values);
+                state.record_collection(true);
+                println!("Startup self-test passed");
+            }
+            Err(e) => {
+                eprintln!("Startup self-test failed: {}", e);
+                if let Some(error::CollectionError::LicenseNotAccepted(_)) = e.downcast_ref() {
+                    eprintln!(
+                        "Run {} once interactively on this host as root and accept the AMD uProf \
+                         license agreement, then restart the exporter",
+                        UPROF_PCM
+                    );
                 }
+                std::process::exit(1);
             }
         }
-    });
+    }
 
-    let addr = ([0, 0, 0, 0], 9100).into();
-    let make_svc = make_service_fn(move |_| {
-        let registry = registry.clone();
+    tokio::spawn(collection_metrics.watchdog().run(
+        COLLECTION_INTERVAL * args.watchdog_intervals,
+        Arc::clone(&state.last_collection),
+        Arc::clone(&state.readiness),
+    ));
+    let (heartbeat, heartbeats) = mpsc::channel(1);
+    tokio::spawn(deadlock_detector.run(COLLECTION_INTERVAL * 3, heartbeats));
+    let mut alerts = alerts::Alerts::new(&config.alerts.rules, &metrics.nodename);
+    let jitter_percent = args.jitter_percent as f64;
+    let snapshot_path = args.snapshot_path.clone();
+    tokio::spawn({
+        let state = Arc::clone(&state);
         async move {
-            Ok::<_, hyper::Error>(service_fn(move |req| {
-                metrics_handler(req, registry.clone())
-            }))
+            // Spread the first AMDuProfPcm run of exporters started together
+            let period = COLLECTION_INTERVAL;
+            let jitter = period.mul_f64(rand::thread_rng().gen_range(0.0..=jitter_percent / 100.0));
+            let mut interval = time::interval_at(time::Instant::now() + jitter, period);
+            loop {
+                // Live scrapes collect right away, even outside the schedule
+                let live_request = tokio::select! {
+                    scheduled = interval.tick() => {
+                        collection_metrics.record_delay(scheduled.elapsed());
+                        None
+                    }
+                    Some(reply) = pending_live.recv() => Some(reply),
+                };
+                // Sent once an iteration completed; a full channel already holds one
+                if live_request.is_none() {
+                    let idle = schedule.as_ref().is_some_and(|schedule| !schedule.is_active());
+                    state.readiness.set_idle(idle);
+                    if idle {
+                        let _ = heartbeat.try_send(());
+                        continue;
+                    }
+                }
+                if let Some(hook) = &pre_collect_hook {
+                    state.phase.set(hook.run().await);
+                }
+                let result = match source.collect(&state.metrics, subprocess.as_ref()).await {
+                    Ok(values) => {
+                        let exported = state.metrics.update(values.clone());
+                        health_score.update(&values);
+                        if let Some(utilization) = &mut mem_bw_utilization {
+                            utilization.update(&values);
+                        }
+                        alerts.evaluate(&exported);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        eprintln!("Error collecting metrics: {}", e);
+                        Err(e.to_string())
+                    }
+                };
+                collection_metrics.record(result.is_ok());
+                if let Some(path) = snapshot_path.as_ref().filter(|_| result.is_ok()) {
+                    if let Err(e) = state.metrics.snapshot().write(path).await {
+                        eprintln!("Failed to write snapshot {}: {}", path.display(), e);
+                    }
+                }
+                for collector in &mut host_collectors {
+                    if let Err(e) = collector.collect() {
+                        eprintln!("Error collecting {} metrics: {}", collector.name(), e);
+                    }
+                }
+                context_switches.update();
+                // A failed collection keeps serving the last good result
+                state.record_collection(result.is_ok());
+                if let Some(reply) = live_request {
+                    let _ = reply.send(result);
+                }
+                let _ = heartbeat.try_send(());
+            }
         }
     });
 
-    let server = Server::bind(&addr).serve(make_svc);
-    println!("AMD uProf Exporter started on :9100");
+    // Leave the final values behind for --restore-snapshot
+    if let Some(path) = args.snapshot_path.clone() {
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            if let Err(e) = state.metrics.snapshot().write(&path).await {
+                eprintln!("Failed to write snapshot {}: {}", path.display(), e);
+            }
+            std::process::exit(0);
+        });
+    }
 
-    if let Err(e) = server.await {
-        eprintln!("Server error: {}", e);
+    let tls = web_config.tls_acceptor().unwrap_or_else(|e| {
+        eprintln!("Failed to set up TLS: {}", e);
+        std::process::exit(1);
+    });
+    // One server per address, all sharing the same state
+    let mut servers = JoinSet::new();
+    for addr in args.listen_addrs.clone() {
+        let listener = match listen::bind(addr, args.ipv6_only).and_then(tokio::net::TcpListener::from_std) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Failed to listen on {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        };
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        println!("AMD uProf Exporter started on {}://{}", scheme, addr);
+        servers.spawn(serve(listener, Arc::clone(&state), tls.clone()));
     }
-}
\ No newline at end of file
+    while servers.join_next().await.is_some() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(content: &str) -> Report {
+        Report {
+            data: output::ReportData::Read(content.to_string()),
+            columns: None,
+        }
+    }
+
+    fn row(count: usize) -> String {
+        (1..=count).map(|value| value.to_string()).collect::<Vec<_>>().join(",")
+    }
+
+    #[test]
+    fn skips_headers() {
+        let header = vec!["Metric"; columns::COUNT].join(",");
+        let content = format!("METRICS\nSystem\n{}\n{}\n", header, row(columns::COUNT));
+        let values = parse_uprof_output(&report(&content), None, |_| true).unwrap();
+        assert_eq!(values[columns::IC_FETCH_MISS_RATIO], 1.0);
+        assert_eq!(values[columns::TOTAL_MEM_WRBW_GBPS], 29.0);
+        assert!(parse_uprof_output(&report(&format!("{}\n", header)), None, |_| true).is_none());
+    }
+
+    #[test]
+    fn skips_rows_of_another_length() {
+        let content = format!("{}\n{}\n", row(columns::COUNT), row(columns::COUNT - 5));
+        let values = parse_uprof_output(&report(&content), None, |_| true).unwrap();
+        assert_eq!(values[columns::TOTAL_MEM_WRBW_GBPS], 29.0);
+        assert!(parse_uprof_output(&report(&row(columns::COUNT - 5)), None, |_| true).is_none());
+        assert!(parse_uprof_output(&report(&row(columns::COUNT + 1)), None, |_| true).is_none());
+    }
+
+    #[test]
+    fn leaves_out_generation_specific_columns_when_undetected() {
+        let zen4 = Some(cpu::ZenGeneration::Zen4);
+        let values = parse_uprof_output(&report(&row(columns::COUNT)), zen4, |_| true).unwrap();
+        assert_eq!(values[columns::L2_ACCESS_FROM_L2_HWPF_PTI], 9.0);
+        let values = parse_uprof_output(&report(&row(columns::COUNT)), None, |_| true).unwrap();
+        assert!(values[columns::L2_ACCESS_FROM_L2_HWPF_PTI].is_nan());
+        assert_eq!(values[columns::TOTAL_MEM_WRBW_GBPS], 29.0);
+        let values = parse_uprof_output(&report(&row(columns::COUNT - 3)), None, |_| true).unwrap();
+        assert!(values[columns::L2_ACCESS_FROM_L2_HWPF_PTI].is_nan());
+        assert_eq!(values[columns::TOTAL_MEM_WRBW_GBPS], 26.0);
+        let zen3 = Some(cpu::ZenGeneration::Zen3);
+        assert!(parse_uprof_output(&report(&row(columns::COUNT)), zen3, |_| true).is_none());
+    }
+
+    #[test]
+    fn lays_out_profiled_columns_only() {
+        let profiled = |column| column >= columns::L3_ACCESS;
+        let values = parse_uprof_output(&report(&row(12)), None, profiled).unwrap();
+        assert!(values[columns::IC_FETCH_MISS_RATIO].is_nan());
+        assert_eq!(values[columns::L3_ACCESS], 1.0);
+        assert_eq!(values[columns::TOTAL_MEM_WRBW_GBPS], 12.0);
+        assert!(parse_uprof_output(&report(&row(columns::COUNT)), None, profiled).is_none());
+        assert!(parse_uprof_output(&report(&row(columns::COUNT)), None, |_| false).is_none());
+    }
+}
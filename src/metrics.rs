@@ -0,0 +1,480 @@
+use crate::registration::{Registrar, RegistrationError};
+use crate::snapshot::MetricsSnapshot;
+use crate::transform::{self, Transform};
+use crate::{cli, columns, config, cpu, k8s};
+use prometheus::core::Collector;
+use prometheus::proto::MetricFamily;
+use prometheus::{GaugeVec, IntCounterVec, Opts, Registry};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name and help of the gauge exported for every uProf column, in report order
+const GAUGES: [(&str, &str); columns::COUNT] = [
+    ("amd_ic_fetch_miss_ratio", "IC Fetch Miss Ratio"),
+    ("amd_op_cache_fetch_miss_ratio", "Op Cache Fetch Miss Ratio"),
+    ("amd_ic_access_pti", "IC Access (pti)"),
+    ("amd_ic_miss_pti", "IC Miss (pti)"),
+    ("amd_dc_access_pti", "DC Access (pti)"),
+    ("amd_l2_access_pti", "L2 Access (pti)"),
+    ("amd_l2_access_from_ic_miss_pti", "L2 Access from IC Miss (pti)"),
+    ("amd_l2_access_from_dc_miss_pti", "L2 Access from DC Miss (pti)"),
+    ("amd_l2_access_from_l2_hwpf_pti", "L2 Access from L2 HWPF (pti)"),
+    ("amd_l2_miss_pti", "L2 Miss (pti)"),
+    ("amd_l2_miss_from_ic_miss_pti", "L2 Miss from IC Miss (pti)"),
+    ("amd_l2_miss_from_dc_miss_pti", "L2 Miss from DC Miss (pti)"),
+    ("amd_l2_miss_from_l2_hwpf_pti", "L2 Miss from L2 HWPF (pti)"),
+    ("amd_l2_hit_pti", "L2 Hit (pti)"),
+    ("amd_l2_hit_from_ic_miss_pti", "L2 Hit from IC Miss (pti)"),
+    ("amd_l2_hit_from_dc_miss_pti", "L2 Hit from DC Miss (pti)"),
+    ("amd_l2_hit_from_l2_hwpf_pti", "L2 Hit from L2 HWPF (pti)"),
+    ("amd_l3_access", "L3 Access"),
+    ("amd_l3_miss", "L3 Miss"),
+    ("amd_l3_miss_percent", "L3 Miss %"),
+    ("amd_l3_hit_percent", "L3 Hit %"),
+    ("amd_ave_l3_miss_latency_ns", "Ave L3 Miss Latency (ns)"),
+    ("amd_total_mem_bw_gbps", "Total Mem Bw (GB/s)"),
+    ("amd_local_dram_read_data_bytes_gbps", "Local DRAM Read Data Bytes(GB/s)"),
+    ("amd_local_dram_write_data_bytes_gbps", "Local DRAM Write Data Bytes(GB/s)"),
+    ("amd_remote_dram_read_data_bytes_gbps", "Remote DRAM Read Data Bytes (GB/s)"),
+    ("amd_remote_dram_write_data_bytes_gbps", "Remote DRAM Write Data Bytes (GB/s)"),
+    ("amd_total_mem_rdbw_gbps", "Total Mem RdBw (GB/s)"),
+    ("amd_total_mem_wrbw_gbps", "Total Mem WrBw (GB/s)"),
+];
+
+/// Column of a metric by its uProf-derived name
+pub fn column_of(name: &str) -> Option<usize> {
+    GAUGES.iter().position(|(gauge, _)| *gauge == name)
+}
+
+/// Column of a metric by the name uProf itself gives it, e.g. "L3 Miss %"
+pub fn column_of_header(header: &str) -> Option<usize> {
+    GAUGES.iter().position(|(_, help)| help.eq_ignore_ascii_case(header.trim()))
+}
+
+/// A slice of the uProf columns exported through its own registry, backed
+/// by one or more AMDuProfPcm profiles
+pub struct MetricGroup {
+    pub name: &'static str,
+    pub profiles: &'static str,
+    pub columns: Range<usize>,
+    registry: Registry,
+    pub enabled: AtomicBool,
+}
+
+/// (name, AMDuProfPcm profiles, columns) of every metric group
+const GROUPS: [(&str, &str, Range<usize>); 3] = [
+    ("cache", "l1,l2", columns::IC_FETCH_MISS_RATIO..columns::L3_ACCESS),
+    ("l3", "l3", columns::L3_ACCESS..columns::TOTAL_MEM_BW_GBPS),
+    ("memory", "memory", columns::TOTAL_MEM_BW_GBPS..columns::COUNT),
+];
+
+pub const GROUP_NAMES: [&str; 3] = [GROUPS[0].0, GROUPS[1].0, GROUPS[2].0];
+
+/// Registry that stamps `--extra-labels` and Kubernetes pod metadata, when
+/// present, on every metric
+pub fn new_registry(args: &cli::Args) -> Registry {
+    let mut labels = k8s::downward_api_labels();
+    labels.extend(args.extra_labels.iter().cloned());
+    if labels.is_empty() {
+        return Registry::new();
+    }
+    Registry::new_custom(None, Some(labels)).unwrap()
+}
+
+/// Gauges of the uProf columns, split into groups with their own registries.
+///
+/// # Locking
+///
+/// `update` takes a complete row by value, checks and transforms it without
+/// any lock, then applies it under the write half of an internal `RwLock`
+/// with plain `.set()` calls only; `gather` holds the read half. Neither
+/// awaits while holding the lock, so `Metrics` can be shared as
+/// `Arc<Metrics>` between the collection loop and HTTP handlers without an
+/// outer `RwLock`, and no lock guard ever lives across an `.await`.
+pub struct Metrics {
+    pub nodename: String,
+    pub groups: Vec<MetricGroup>,
+    /// One gauge per column, `None` for columns the CPU does not report
+    gauges: Vec<Option<GaugeVec>>,
+    /// Gauges set alongside each column's own, from `[aliases]` and the
+    /// uProf names kept by `--rename-to-conventions`, with the factor each is
+    /// exported with
+    aliases: Vec<Vec<(GaugeVec, f64)>>,
+    /// Factor applied to each column before it is exported
+    scales: Vec<f64>,
+    transforms: Vec<Box<dyn Transform>>,
+    bounds_violations: IntCounterVec,
+    /// Skip gauges whose value moved by no more than this (`--delta-parse`)
+    delta_epsilon: Option<f64>,
+    /// Unregister gauges that read zero this many times in a row
+    prune_zero_after: Option<u32>,
+    /// Held for writing while a row is applied so scrapes never see half of it
+    applied: RwLock<Applied>,
+}
+
+/// Per-column state of the gauges
+struct Applied {
+    /// Value last set, NaN when unset
+    values: Vec<f64>,
+    /// Whether the gauge is in its group's registry, see `--lazy-registration`
+    /// and `--prune-zero-metrics`
+    registered: Vec<bool>,
+    /// Consecutive collections that read zero
+    zero_streaks: Vec<u32>,
+    updated_at: Option<SystemTime>,
+}
+
+/// Prometheus-style replacement for a uProf metric name and the factor that
+/// converts the value into the new unit
+fn conventional_name(name: &str) -> Option<(String, f64)> {
+    if let Some(base) = name.strip_suffix("_pti") {
+        return Some((format!("{}_per_thousand_instructions", base), 1.0));
+    }
+    if let Some(base) = name.strip_suffix("_gbps") {
+        let base = base.strip_suffix("_bytes").unwrap_or(base);
+        return Some((format!("{}_bytes_per_second", base), 1e9));
+    }
+    None
+}
+
+impl Metrics {
+    /// Registers every gauge through `registrar`, which also catches names
+    /// taken in another group or by another collector; with
+    /// `--lazy-registration` the names are only reserved. Fails with every
+    /// gauge that could not be registered.
+    pub fn try_new(
+        args: &cli::Args,
+        config: &config::Config,
+        generation: Option<cpu::ZenGeneration>,
+        nodename: &str,
+        registrar: &Registrar,
+    ) -> Result<Self, Vec<RegistrationError>> {
+        registrar.scoped(|registrar| Self::new(args, config, generation, nodename, registrar))
+    }
+
+    fn new(
+        args: &cli::Args,
+        config: &config::Config,
+        generation: Option<cpu::ZenGeneration>,
+        nodename: &str,
+        registrar: &Registrar,
+    ) -> Self {
+        let help_overrides = &config.help_overrides;
+        let mut scales = vec![1.0; columns::COUNT];
+
+        for name in help_overrides.keys() {
+            if !GAUGES.iter().any(|(gauge, _)| gauge == name) {
+                eprintln!("Warning: help override for unknown metric {}", name);
+            }
+        }
+        for name in config.aliases.keys() {
+            if column_of(name).is_none() {
+                eprintln!("Warning: alias for unknown metric {}", name);
+            }
+        }
+
+        let groups: Vec<MetricGroup> = GROUPS
+            .into_iter()
+            .map(|(name, profiles, columns)| MetricGroup {
+                name,
+                profiles,
+                columns,
+                registry: new_registry(args),
+                enabled: AtomicBool::new(args.enable_groups.iter().any(|group| group == name)),
+            })
+            .collect();
+
+        let mut aliases = vec![Vec::new(); columns::COUNT];
+        let gauges = GAUGES
+            .iter()
+            .enumerate()
+            .map(|(column, &(name, help))| {
+                // Metrics the detected generation cannot report are left unregistered
+                if !cpu::exports(generation, column) {
+                    return None;
+                }
+                let help = help_overrides.get(name).map_or(help, String::as_str);
+                let mut column_aliases = Vec::new();
+                let opts = match conventional_name(name).filter(|_| args.rename_to_conventions) {
+                    Some((renamed, scale)) => {
+                        scales[column] = scale;
+                        // The uProf name stays exported, unscaled, until dashboards moved over
+                        if !args.drop_legacy_names {
+                            let help = format!("{} (deprecated, use {})", help, renamed);
+                            column_aliases.push((GaugeVec::new(Opts::new(name, help), &["nodename"]).unwrap(), 1.0));
+                        }
+                        Opts::new(renamed, format!("{} (formerly {})", help, name))
+                    }
+                    None => Opts::new(name, help),
+                };
+                let gauge = GaugeVec::new(opts, &["nodename"]).unwrap();
+                if let Some(alias) = config.aliases.get(name) {
+                    let opts = Opts::new(alias, format!("{} (alias of {})", help, name));
+                    column_aliases.push((GaugeVec::new(opts, &["nodename"]).unwrap(), scales[column]));
+                }
+                let group = groups.iter().find(|group| group.columns.contains(&column)).unwrap();
+                for gauge in std::iter::once(&gauge).chain(column_aliases.iter().map(|(alias, _)| alias)) {
+                    match args.lazy_registration {
+                        true => {
+                            registrar.claim(gauge);
+                        }
+                        false => registrar.register_in(&group.registry, gauge),
+                    }
+                }
+                aliases[column] = column_aliases;
+                Some(gauge)
+            })
+            .collect();
+
+        let bounds_violations = IntCounterVec::new(
+            Opts::new(
+                "amd_uprof_bounds_violations_total",
+                "uProf values outside their physically possible range after transforms, exported as NaN",
+            ),
+            &["nodename", "metric_name"],
+        ).unwrap();
+        registrar.register(&bounds_violations);
+
+        Self {
+            nodename: nodename.to_string(),
+            groups,
+            gauges,
+            aliases,
+            scales,
+            transforms: transform::pipeline(&config.transforms),
+            bounds_violations,
+            delta_epsilon: args.delta_parse.then_some(args.delta_epsilon),
+            prune_zero_after: args.prune_zero_metrics,
+            applied: RwLock::new(Applied {
+                values: vec![f64::NAN; columns::COUNT],
+                registered: vec![!args.lazy_registration; columns::COUNT],
+                zero_streaks: vec![0; columns::COUNT],
+                updated_at: None,
+            }),
+        }
+    }
+
+    pub fn enabled_groups(&self) -> impl Iterator<Item = &MetricGroup> {
+        self.groups.iter().filter(|group| group.enabled.load(Ordering::Relaxed))
+    }
+
+    pub fn is_column_enabled(&self, column: usize) -> bool {
+        self.enabled_groups().any(|group| group.columns.contains(&column))
+    }
+
+    /// Number of gauges of a group the detected generation supports, whether
+    /// or not they are registered yet
+    pub fn group_metric_count(&self, group: &MetricGroup) -> usize {
+        self.gauges
+            .iter()
+            .enumerate()
+            .filter(|(column, gauge)| gauge.is_some() && group.columns.contains(column))
+            .count()
+    }
+
+    /// Enables or disables a group at runtime; values of a disabled group are
+    /// dropped so that re-enabling it does not resurrect stale samples.
+    /// Returns false for unknown groups.
+    pub fn set_group_enabled(&self, name: &str, enabled: bool) -> bool {
+        let Some(group) = self.groups.iter().find(|group| group.name == name) else {
+            return false;
+        };
+        let mut applied = self.applied.write().unwrap();
+        group.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            let columns = group.columns.clone();
+            let aliases = self.aliases[columns.clone()].iter().flatten().map(|(alias, _)| alias);
+            for gauge in self.gauges[columns].iter().flatten().chain(aliases) {
+                gauge.reset();
+            }
+            applied.values[group.columns.clone()].fill(f64::NAN);
+        }
+        true
+    }
+
+    /// Metric families of the enabled groups
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        let _applied = self.applied.read().unwrap();
+        self.enabled_groups()
+            .flat_map(|group| group.registry.gather())
+            .collect()
+    }
+
+    /// Values of the registered gauges of the enabled groups
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let applied = self.applied.read().unwrap();
+        let values = self
+            .gauges
+            .iter()
+            .enumerate()
+            .filter_map(|(column, gauge)| {
+                let value = applied.values[column];
+                if value.is_nan() || !applied.registered[column] || !self.is_column_enabled(column) {
+                    return None;
+                }
+                Some((gauge.as_ref()?.desc()[0].fq_name.clone(), value * self.scales[column]))
+            })
+            .collect::<BTreeMap<_, _>>();
+        let collected_at = applied
+            .updated_at
+            .map(|time| time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64());
+        MetricsSnapshot {
+            nodename: self.nodename.clone(),
+            collected_at,
+            values,
+        }
+    }
+
+    /// Transforms, checks and applies a collected row; returns it as the
+    /// gauges got it, before `--rename-to-conventions` scaling
+    pub fn update(&self, mut values: Vec<f64>) -> Vec<f64> {
+        let reported: Vec<bool> = values.iter().map(|value| !value.is_nan()).collect();
+        // Bounds hold for the values exported, so transforms come first
+        for transform in &self.transforms {
+            transform.apply(&mut values);
+        }
+        for (column, value) in values.iter_mut().enumerate() {
+            let (min, max) = columns::bounds(column);
+            if *value < min || *value > max {
+                let name = GAUGES[column].0;
+                eprintln!("Out of bounds value {} of {}, exporting NaN", value, name);
+                self.bounds_violations.with_label_values(&[&self.nodename, name]).inc();
+                *value = f64::NAN;
+            }
+        }
+        // A reported value that a transform or the bounds turned into NaN is
+        // exported as NaN; columns the source left out keep their value
+        let cleared: Vec<bool> =
+            reported.iter().zip(&values).map(|(reported, value)| *reported && value.is_nan()).collect();
+        self.apply(values.clone(), &cleared);
+        values
+    }
+
+    /// Pre-populates the gauges from a snapshot taken before a restart;
+    /// returns how many values were restored
+    pub fn restore(&self, snapshot: &MetricsSnapshot) -> usize {
+        let mut values = vec![f64::NAN; columns::COUNT];
+        for (column, gauge) in self.gauges.iter().enumerate() {
+            let Some(gauge) = gauge else {
+                continue;
+            };
+            if let Some(value) = snapshot.values.get(&gauge.desc()[0].fq_name) {
+                values[column] = value / self.scales[column];
+            }
+        }
+        let restored = values.iter().filter(|value| !value.is_nan()).count();
+        self.apply(values, &[false; columns::COUNT]);
+        restored
+    }
+
+    /// Sets the gauges to a checked and transformed row; NaN leaves a gauge
+    /// as it is unless the column is `cleared`, then it is exported as NaN
+    fn apply(&self, values: Vec<f64>, cleared: &[bool]) {
+        let mut applied = self.applied.write().unwrap();
+        applied.updated_at = Some(SystemTime::now());
+        for (column, ((gauge, value), scale)) in self.gauges.iter().zip(values).zip(&self.scales).enumerate() {
+            if let Some(gauge) = gauge {
+                if !self.is_column_enabled(column) {
+                    continue;
+                }
+                let aliases = &self.aliases[column];
+                if value.is_nan() {
+                    if cleared[column] && applied.registered[column] {
+                        gauge.with_label_values(&[&self.nodename]).set(f64::NAN);
+                        for (alias, _) in aliases {
+                            alias.with_label_values(&[&self.nodename]).set(f64::NAN);
+                        }
+                        applied.values[column] = f64::NAN;
+                    }
+                    continue;
+                }
+                let name = &gauge.desc()[0].fq_name;
+                let group = self.groups.iter().find(|group| group.columns.contains(&column)).unwrap();
+                applied.zero_streaks[column] = match value == 0.0 {
+                    true => applied.zero_streaks[column].saturating_add(1),
+                    false => 0,
+                };
+                if let Some(limit) = self.prune_zero_after.filter(|limit| applied.zero_streaks[column] >= *limit) {
+                    if applied.registered[column] {
+                        for gauge in std::iter::once(gauge).chain(aliases.iter().map(|(alias, _)| alias)) {
+                            let _ = group.registry.unregister(Box::new(gauge.clone()));
+                        }
+                        println!("Pruned {} after {} collections at zero", name, limit);
+                        applied.registered[column] = false;
+                        applied.values[column] = f64::NAN;
+                    }
+                    continue;
+                }
+                if !applied.registered[column] {
+                    if let Err(e) = group.registry.register(Box::new(gauge.clone())) {
+                        eprintln!("Failed to register {}: {}", name, e);
+                        continue;
+                    }
+                    for (alias, _) in aliases {
+                        if let Err(e) = group.registry.register(Box::new(alias.clone())) {
+                            eprintln!("Failed to register {}: {}", alias.desc()[0].fq_name, e);
+                        }
+                    }
+                    println!("Registered {}", name);
+                    applied.registered[column] = true;
+                }
+                // NaN never compares as unchanged, so unset gauges are always set
+                let unchanged =
+                    self.delta_epsilon.is_some_and(|epsilon| (value - applied.values[column]).abs() <= epsilon);
+                if !unchanged {
+                    gauge.with_label_values(&[&self.nodename]).set(value * scale);
+                    for (alias, scale) in aliases {
+                        alias.with_label_values(&[&self.nodename]).set(value * scale);
+                    }
+                    applied.values[column] = value;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn exports_nan_from_transforms() {
+        let args = cli::Args::parse_from(["uprof-exporter"]);
+        let config: config::Config =
+            toml::from_str("[[transforms]]\ntype = \"replace\"\nindex = 21\nfrom = -1.0\nto = nan\n").unwrap();
+        let registrar = Registrar::new(Registry::new());
+        let metrics = Metrics::try_new(&args, &config, None, "node", &registrar).unwrap();
+        let value = |column: usize| metrics.gauges[column].as_ref().unwrap().with_label_values(&["node"]).get();
+
+        let mut row = vec![f64::NAN; columns::COUNT];
+        row[columns::IC_FETCH_MISS_RATIO] = 0.5;
+        row[columns::AVE_L3_MISS_LATENCY_NS] = 80.0;
+        metrics.update(row);
+        assert_eq!(value(columns::AVE_L3_MISS_LATENCY_NS), 80.0);
+
+        // The sentinel becomes NaN, a column missing from the row keeps its value
+        let mut row = vec![f64::NAN; columns::COUNT];
+        row[columns::AVE_L3_MISS_LATENCY_NS] = -1.0;
+        metrics.update(row);
+        assert!(value(columns::AVE_L3_MISS_LATENCY_NS).is_nan());
+        assert_eq!(value(columns::IC_FETCH_MISS_RATIO), 0.5);
+    }
+
+    #[test]
+    fn reports_every_name_collision() {
+        let args = cli::Args::parse_from(["uprof-exporter"]);
+        let config: config::Config = toml::from_str(
+            "[aliases]\namd_ic_fetch_miss_ratio = \"amd_ic_access_pti\"\namd_ic_access_pti = \"amd_ic_fetch_miss_ratio\"\n",
+        )
+        .unwrap();
+        let registrar = Registrar::new(Registry::new());
+        let errors = Metrics::try_new(&args, &config, None, "node", &registrar).err().unwrap();
+        assert_eq!(errors.len(), 2);
+        // Taken out of the registrar, so they are not reported twice
+        assert!(registrar.finish().is_ok());
+    }
+}
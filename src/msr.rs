@@ -0,0 +1,264 @@
+use crate::columns;
+use crate::cpu::ZenGeneration;
+use nix::sys::uio::{pread, pwrite};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::time::Instant;
+
+// L3 and Data Fabric PMC register pairs (PPR for AMD Family 19h)
+const L3_PMC_CFG: u64 = 0xC001_0230;
+const L3_PMC_CTR: u64 = 0xC001_0231;
+const DF_PMC_CFG: u64 = 0xC001_0240;
+const DF_PMC_CTR: u64 = 0xC001_0241;
+const DF_COUNTERS: usize = 4;
+
+const PMC_ENABLE: u64 = 1 << 22;
+const L3_ALL_SLICES: u64 = 1 << 46;
+const L3_ALL_CORES: u64 = 1 << 47;
+const L3_ALL_THREADS: u64 = 0b11 << 56;
+
+// L3LookupState: umask 0xFF counts all accesses, 0x01 only misses
+const L3_LOOKUP_STATE: u64 = 0x04;
+
+pub(crate) const BYTES_PER_BEAT: f64 = 64.0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Direction {
+    Read,
+    Write,
+    Total,
+}
+
+pub(crate) struct DfEvent {
+    pub direction: Direction,
+    pub event: u64,
+    pub umask: u64,
+}
+
+struct Msr {
+    file: File,
+}
+
+impl Msr {
+    fn open(cpu: u32) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("/dev/cpu/{}/msr", cpu))?;
+        Ok(Self { file })
+    }
+
+    fn read(&self, reg: u64) -> nix::Result<u64> {
+        let mut buf = [0u8; 8];
+        pread(&self.file, &mut buf, reg as i64)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn write(&self, reg: u64, value: u64) -> nix::Result<()> {
+        pwrite(&self.file, &value.to_le_bytes(), reg as i64)?;
+        Ok(())
+    }
+
+    /// Reads a counter and resets it to zero
+    fn take(&self, reg: u64) -> nix::Result<u64> {
+        let value = self.read(reg)?;
+        self.write(reg, 0)?;
+        Ok(value)
+    }
+}
+
+/// Samples L3 and DRAM counters straight from `/dev/cpu/*/msr`.
+///
+/// Data Fabric has only four counters per socket while there is a read and a
+/// write event per DRAM channel, so channels are measured in rotating groups
+/// and the last known rate of every channel is summed up.
+pub struct MsrSampler {
+    l3: Vec<Msr>,
+    df: Vec<Msr>,
+    df_events: Vec<DfEvent>,
+    df_rates: Vec<Vec<f64>>,
+    df_group: usize,
+    last: Instant,
+}
+
+impl MsrSampler {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let generation = ZenGeneration::detect()
+            .filter(|generation| matches!(generation, ZenGeneration::Zen3 | ZenGeneration::Zen4))
+            .ok_or("MSR sampling supports only AMD Zen 3 and Zen 4")?;
+
+        let l3 = first_cpus("cache/index3/shared_cpu_list")?
+            .into_iter()
+            .map(Msr::open)
+            .collect::<Result<Vec<_>, _>>()?;
+        let df = first_cpus("topology/physical_package_id")?
+            .into_iter()
+            .map(Msr::open)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let df_events = df_events(generation);
+        let df_rates = vec![vec![f64::NAN; df_events.len()]; df.len()];
+
+        let sampler = Self {
+            l3,
+            df,
+            df_events,
+            df_rates,
+            df_group: 0,
+            last: Instant::now(),
+        };
+        sampler.program_l3()?;
+        sampler.program_df()?;
+        Ok(sampler)
+    }
+
+    fn program_l3(&self) -> nix::Result<()> {
+        let base = L3_LOOKUP_STATE | PMC_ENABLE | L3_ALL_SLICES | L3_ALL_CORES | L3_ALL_THREADS;
+        for msr in &self.l3 {
+            msr.write(L3_PMC_CFG, base | 0xFF << 8)?;
+            msr.write(L3_PMC_CFG + 2, base | 0x01 << 8)?;
+            msr.write(L3_PMC_CTR, 0)?;
+            msr.write(L3_PMC_CTR + 2, 0)?;
+        }
+        Ok(())
+    }
+
+    fn program_df(&self) -> nix::Result<()> {
+        for msr in &self.df {
+            for slot in 0..DF_COUNTERS {
+                let reg = 2 * slot as u64;
+                let config = self
+                    .df_events
+                    .get(self.df_group * DF_COUNTERS + slot)
+                    .map_or(0, |event| df_config(event.event, event.umask) | PMC_ENABLE);
+                msr.write(DF_PMC_CFG + reg, config)?;
+                msr.write(DF_PMC_CTR + reg, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a row laid out like AMDuProfPcm output, with NaN in columns
+    /// that cannot be derived from raw counters
+    pub fn sample(&mut self) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+        let elapsed = self.last.elapsed().as_secs_f64();
+        self.last = Instant::now();
+
+        let mut values = vec![f64::NAN; columns::COUNT];
+
+        let mut access = 0u64;
+        let mut miss = 0u64;
+        for msr in &self.l3 {
+            access += msr.take(L3_PMC_CTR)?;
+            miss += msr.take(L3_PMC_CTR + 2)?;
+        }
+        values[columns::L3_ACCESS] = access as f64 / elapsed;
+        values[columns::L3_MISS] = miss as f64 / elapsed;
+        if access > 0 {
+            let miss_percent = miss as f64 * 100.0 / access as f64;
+            values[columns::L3_MISS_PERCENT] = miss_percent;
+            values[columns::L3_HIT_PERCENT] = 100.0 - miss_percent;
+        }
+
+        let first = self.df_group * DF_COUNTERS;
+        for (msr, rates) in self.df.iter().zip(self.df_rates.iter_mut()) {
+            for slot in 0..DF_COUNTERS.min(self.df_events.len() - first) {
+                let beats = msr.take(DF_PMC_CTR + 2 * slot as u64)?;
+                rates[first + slot] = beats as f64 * BYTES_PER_BEAT / elapsed / 1e9;
+            }
+        }
+        self.df_group = (self.df_group + 1) % self.df_events.len().div_ceil(DF_COUNTERS);
+        self.program_df()?;
+
+        let read = self.df_bandwidth(Direction::Read);
+        let write = self.df_bandwidth(Direction::Write);
+        values[columns::TOTAL_MEM_RDBW_GBPS] = read;
+        values[columns::TOTAL_MEM_WRBW_GBPS] = write;
+        values[columns::TOTAL_MEM_BW_GBPS] = if self.has_direction(Direction::Total) {
+            self.df_bandwidth(Direction::Total)
+        } else {
+            read + write
+        };
+
+        Ok(values)
+    }
+
+    fn has_direction(&self, direction: Direction) -> bool {
+        self.df_events.iter().any(|event| event.direction == direction)
+    }
+
+    /// Sum of the last known rates; NaN until every channel has been measured once
+    fn df_bandwidth(&self, direction: Direction) -> f64 {
+        if !self.has_direction(direction) {
+            return f64::NAN;
+        }
+        self.df_rates
+            .iter()
+            .flat_map(|rates| rates.iter().zip(&self.df_events))
+            .filter(|(_, event)| event.direction == direction)
+            .map(|(rate, _)| *rate)
+            .sum()
+    }
+}
+
+impl Drop for MsrSampler {
+    fn drop(&mut self) {
+        for msr in &self.l3 {
+            let _ = msr.write(L3_PMC_CFG, 0);
+            let _ = msr.write(L3_PMC_CFG + 2, 0);
+        }
+        for msr in &self.df {
+            for slot in 0..DF_COUNTERS {
+                let _ = msr.write(DF_PMC_CFG + 2 * slot as u64, 0);
+            }
+        }
+    }
+}
+
+/// `DF_PMC_CFG` encoding of a Data Fabric event
+fn df_config(event: u64, umask: u64) -> u64 {
+    (event & 0xFF) | ((event >> 8) & 0x3F) << 32 | (umask & 0xFF) << 8 | ((umask >> 8) & 0xF) << 24
+}
+
+pub(crate) fn df_events(generation: ZenGeneration) -> Vec<DfEvent> {
+    match generation {
+        // Not known here, the PPR of the family has them
+        ZenGeneration::Zen2 | ZenGeneration::Zen5 => Vec::new(),
+        // DramChannelDataController: 64B requests of both directions, 8 channels
+        ZenGeneration::Zen3 => (0..8)
+            .map(|channel| DfEvent {
+                direction: Direction::Total,
+                event: 0x07 + 0x40 * channel,
+                umask: 0x38,
+            })
+            .collect(),
+        // LocalProcessor{Read,Write}DataBeats per coherent slave, 12 channels
+        ZenGeneration::Zen4 => (0..12)
+            .flat_map(|channel| {
+                let event = 0x1F + 0x40 * channel;
+                [
+                    DfEvent { direction: Direction::Read, event, umask: 0x7FE },
+                    DfEvent { direction: Direction::Write, event, umask: 0x7FF },
+                ]
+            })
+            .collect(),
+    }
+}
+
+/// Picks the lowest numbered CPU for every distinct value of a sysfs
+/// topology attribute, e.g. one CPU per socket or per L3 complex
+fn first_cpus(attribute: &str) -> std::io::Result<Vec<u32>> {
+    let mut cpus: Vec<u32> = fs::read_dir("/dev/cpu")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    cpus.sort_unstable();
+
+    let mut first = BTreeMap::new();
+    for cpu in cpus {
+        let path = format!("/sys/devices/system/cpu/cpu{}/{}", cpu, attribute);
+        if let Ok(key) = fs::read_to_string(path) {
+            first.entry(key.trim().to_string()).or_insert(cpu);
+        }
+    }
+    Ok(first.into_values().collect())
+}
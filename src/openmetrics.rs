@@ -0,0 +1,221 @@
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use prometheus::Encoder;
+use std::io::Write;
+
+pub const FORMAT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Terminates every OpenMetrics exposition
+pub const EOF: &str = "# EOF\n";
+
+/// Whether the client asked for OpenMetrics, as Prometheus does by default
+pub fn accepted(accept: Option<&str>) -> bool {
+    accept.is_some_and(|accept| accept.contains("application/openmetrics-text"))
+}
+
+/// Label set of an exemplar may not exceed this many characters
+const MAX_EXEMPLAR_LABELS_LEN: usize = 128;
+
+/// Trace ID attached to the samples of some gauge families
+pub struct Exemplar<'a> {
+    pub trace_id: &'a str,
+    pub families: &'a [&'a str],
+}
+
+/// Encodes metric families in the OpenMetrics text format, without the
+/// final `# EOF` so that families can be encoded one at a time
+pub struct OpenMetricsEncoder<'a> {
+    /// Appended to every sample, in seconds since unix epoch
+    timestamp: Option<f64>,
+    exemplar: Option<Exemplar<'a>>,
+}
+
+impl<'a> OpenMetricsEncoder<'a> {
+    pub fn new(timestamp: Option<f64>) -> Self {
+        Self { timestamp, exemplar: None }
+    }
+
+    /// Trace IDs too long for an exemplar are left out
+    pub fn exemplar(mut self, exemplar: Exemplar<'a>) -> Self {
+        let labels_len = "trace_id".len() + exemplar.trace_id.chars().count();
+        if labels_len <= MAX_EXEMPLAR_LABELS_LEN {
+            self.exemplar = Some(exemplar);
+        }
+        self
+    }
+
+    fn exemplar_for(&self, family: &str) -> Option<&str> {
+        let exemplar = self.exemplar.as_ref()?;
+        exemplar.families.contains(&family).then_some(exemplar.trace_id)
+    }
+
+    fn sample(
+        &self,
+        writer: &mut dyn Write,
+        name: &str,
+        labels: &[LabelPair],
+        extra: Option<(&str, f64)>,
+        value: f64,
+        trace_id: Option<&str>,
+    ) -> std::io::Result<()> {
+        write!(writer, "{}", name)?;
+        let mut pairs: Vec<(&str, String)> =
+            labels.iter().map(|label| (label.get_name(), escape(label.get_value()))).collect();
+        if let Some((name, bound)) = extra {
+            pairs.push((name, number(bound)));
+        }
+        if !pairs.is_empty() {
+            let pairs: Vec<String> = pairs.iter().map(|(name, value)| format!("{}=\"{}\"", name, value)).collect();
+            write!(writer, "{{{}}}", pairs.join(","))?;
+        }
+        write!(writer, " {}", number(value))?;
+        if let Some(timestamp) = self.timestamp {
+            write!(writer, " {}", timestamp)?;
+        }
+        if let Some(trace_id) = trace_id {
+            write!(writer, " # {{trace_id=\"{}\"}} {}", escape(trace_id), number(value))?;
+        }
+        writeln!(writer)
+    }
+}
+
+impl Encoder for OpenMetricsEncoder<'_> {
+    fn encode<W: Write>(&self, families: &[MetricFamily], writer: &mut W) -> prometheus::Result<()> {
+        for family in families {
+            let name = family.get_name();
+            let field_type = family.get_field_type();
+            // Counter families are named without the suffix of their samples
+            let family_name = match field_type {
+                MetricType::COUNTER => name.strip_suffix("_total").unwrap_or(name),
+                _ => name,
+            };
+            let type_name = match field_type {
+                MetricType::COUNTER => "counter",
+                MetricType::GAUGE => "gauge",
+                MetricType::HISTOGRAM => "histogram",
+                MetricType::SUMMARY => "summary",
+                MetricType::UNTYPED => "unknown",
+            };
+            writeln!(writer, "# TYPE {} {}", family_name, type_name)?;
+            if !family.get_help().is_empty() {
+                writeln!(writer, "# HELP {} {}", family_name, escape(family.get_help()))?;
+            }
+            for metric in family.get_metric() {
+                let labels = metric.get_label();
+                match field_type {
+                    MetricType::COUNTER => {
+                        let name = format!("{}_total", family_name);
+                        self.sample(writer, &name, labels, None, metric.get_counter().get_value(), None)?;
+                    }
+                    MetricType::GAUGE => {
+                        let trace_id = self.exemplar_for(name);
+                        self.sample(writer, name, labels, None, metric.get_gauge().get_value(), trace_id)?;
+                    }
+                    MetricType::UNTYPED => {
+                        self.sample(writer, name, labels, None, metric.get_untyped().get_value(), None)?;
+                    }
+                    MetricType::HISTOGRAM => {
+                        let histogram = metric.get_histogram();
+                        let bucket = format!("{}_bucket", name);
+                        for b in histogram.get_bucket() {
+                            let le = Some(("le", b.get_upper_bound()));
+                            self.sample(writer, &bucket, labels, le, b.get_cumulative_count() as f64, None)?;
+                        }
+                        // The +Inf bucket is implicit in the protobuf model
+                        if histogram.get_bucket().last().is_none_or(|b| b.get_upper_bound().is_finite()) {
+                            let le = Some(("le", f64::INFINITY));
+                            self.sample(writer, &bucket, labels, le, histogram.get_sample_count() as f64, None)?;
+                        }
+                        let (count, sum) = (histogram.get_sample_count() as f64, histogram.get_sample_sum());
+                        self.sample(writer, &format!("{}_count", name), labels, None, count, None)?;
+                        self.sample(writer, &format!("{}_sum", name), labels, None, sum, None)?;
+                    }
+                    MetricType::SUMMARY => {
+                        let summary = metric.get_summary();
+                        for q in summary.get_quantile() {
+                            let quantile = Some(("quantile", q.get_quantile()));
+                            self.sample(writer, name, labels, quantile, q.get_value(), None)?;
+                        }
+                        let (count, sum) = (summary.get_sample_count() as f64, summary.get_sample_sum());
+                        self.sample(writer, &format!("{}_count", name), labels, None, count, None)?;
+                        self.sample(writer, &format!("{}_sum", name), labels, None, sum, None)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn format_type(&self) -> &str {
+        FORMAT_TYPE
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn number(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        value if value.is_nan() => "NaN".to_string(),
+        value => format!("{:?}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{GaugeVec, IntCounter, Opts, Registry};
+
+    #[test]
+    fn encodes_with_timestamps() {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(Opts::new("amd_l3_miss_percent", "L3 \"miss\" percent"), &["nodename"]).unwrap();
+        let counter = IntCounter::new("amd_uprof_truncated_total", "Truncated responses").unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        gauge.with_label_values(&["node\\1"]).set(12.5);
+        counter.inc();
+
+        let mut buffer = Vec::new();
+        OpenMetricsEncoder::new(Some(1700000000.5)).encode(&registry.gather(), &mut buffer).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "# TYPE amd_l3_miss_percent gauge\n\
+             # HELP amd_l3_miss_percent L3 \\\"miss\\\" percent\n\
+             amd_l3_miss_percent{nodename=\"node\\\\1\"} 12.5 1700000000.5\n\
+             # TYPE amd_uprof_truncated counter\n\
+             # HELP amd_uprof_truncated Truncated responses\n\
+             amd_uprof_truncated_total 1.0 1700000000.5\n"
+        );
+    }
+
+    #[test]
+    fn attaches_exemplars_to_listed_gauges() {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(Opts::new("amd_total_mem_bw_gbps", "Total"), &["nodename"]).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        gauge.with_label_values(&["vm"]).set(40.0);
+
+        let families = ["amd_total_mem_bw_gbps"];
+        let encode = |trace_id: &str| {
+            let mut buffer = Vec::new();
+            OpenMetricsEncoder::new(None)
+                .exemplar(Exemplar { trace_id, families: &families })
+                .encode(&registry.gather(), &mut buffer)
+                .unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+        assert!(encode("4bf92f35").ends_with("amd_total_mem_bw_gbps{nodename=\"vm\"} 40.0 # {trace_id=\"4bf92f35\"} 40.0\n"));
+        assert!(encode(&"f".repeat(121)).ends_with("amd_total_mem_bw_gbps{nodename=\"vm\"} 40.0\n"));
+    }
+
+    #[test]
+    fn negotiates_from_accept() {
+        let prometheus = "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5,*/*;q=0.1";
+        assert!(accepted(Some(prometheus)));
+        assert!(!accepted(Some("text/plain")));
+        assert!(!accepted(None));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::error::CollectionError;
+use crate::glob;
+use chrono::format::{Item, StrftimeItems};
+use chrono::Local;
+use memmap2::Mmap;
+use ring::digest::{digest, SHA256};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Accepts report paths whose strftime specifiers chrono understands, in
+/// the file name only, after some literal text; cleanup relies on both to
+/// find old reports without touching other files
+pub fn parse_template(template: &str) -> Result<String, String> {
+    if StrftimeItems::new(template).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("invalid strftime specifier in {}", template));
+    }
+    let path = Path::new(template);
+    if path.parent().is_some_and(|dir| dir.to_string_lossy().contains('%')) {
+        return Err(format!("the directory of {} cannot contain strftime specifiers", template));
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if name.is_empty() || name.starts_with('%') {
+        return Err(format!("the file name of {} must start with literal text", template));
+    }
+    Ok(template.to_string())
+}
+
+/// Glob of the file names `template` expands to, with `infix` between the
+/// stem and the extension; strftime specifiers match anything
+fn file_name_glob(template: &str, infix: &str) -> String {
+    let template = Path::new(template);
+    let stem = template.file_stem().unwrap_or_default().to_string_lossy();
+    let mut glob: String = StrftimeItems::new(&stem)
+        .map(|item| match item {
+            Item::Literal(text) | Item::Space(text) => text.to_string(),
+            Item::OwnedLiteral(text) | Item::OwnedSpace(text) => text.to_string(),
+            _ => "*".to_string(),
+        })
+        .collect();
+    glob.push_str(infix);
+    if let Some(extension) = template.extension() {
+        glob.push('.');
+        glob.push_str(&extension.to_string_lossy());
+    }
+    glob
+}
+
+/// Contents of a report, read onto the heap or memory-mapped
+pub enum ReportData {
+    Read(String),
+    Mapped(Mmap),
+}
+
+impl ReportData {
+    pub async fn load(path: &Path, mmap: bool) -> std::io::Result<Self> {
+        if !mmap {
+            return Ok(ReportData::Read(tokio::fs::read_to_string(path).await?));
+        }
+        let file = std::fs::File::open(path)?;
+        // SAFETY: AMDuProfPcm has exited and nothing else writes the report;
+        // the mapping stays valid after the file is moved or deleted
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(ReportData::Mapped(map))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            ReportData::Read(content) => content.as_bytes(),
+            ReportData::Mapped(map) => map,
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&str, std::str::Utf8Error> {
+        match self {
+            ReportData::Read(content) => Ok(content),
+            ReportData::Mapped(map) => std::str::from_utf8(map),
+        }
+    }
+}
+
+/// File written next to a report, e.g. `report.csv.sha256`
+fn sidecar(report: &Path, extension: &str) -> PathBuf {
+    let mut name = report.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Metadata files uProf may write next to a report, in order of preference
+const METADATA_EXTENSIONS: [&str; 2] = ["meta", "json"];
+
+#[derive(Deserialize)]
+struct Metadata {
+    columns: Vec<ColumnMetadata>,
+}
+
+/// Units and descriptions are there too but unused
+#[derive(Deserialize)]
+struct ColumnMetadata {
+    name: String,
+}
+
+/// Column names, in report order, from the metadata file of a report;
+/// `None` when there is none or it cannot be parsed
+pub async fn read_metadata(report: &Path) -> Option<Vec<String>> {
+    for extension in METADATA_EXTENSIONS {
+        let path = sidecar(report, extension);
+        let Ok(content) = tokio::fs::read_to_string(&path).await else {
+            continue;
+        };
+        match serde_json::from_str::<Metadata>(&content) {
+            Ok(metadata) => return Some(metadata.columns.into_iter().map(|column| column.name).collect()),
+            Err(e) => eprintln!("Ignoring report metadata {}: {}", path.display(), e),
+        }
+    }
+    None
+}
+
+/// Compares a report with the checksum in its `.sha256` sidecar, catching
+/// reports that were only partially written
+pub async fn verify_checksum(report: &Path, content: &[u8]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let sidecar = sidecar(report, "sha256");
+    let expected = tokio::fs::read_to_string(&sidecar)
+        .await
+        .map_err(|e| format!("cannot read checksum {}: {}", sidecar.display(), e))?;
+    let expected = expected.split_whitespace().next().unwrap_or_default().to_lowercase();
+    let actual: String = digest(&SHA256, content).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+    if actual != expected {
+        return Err(CollectionError::ChecksumMismatch(report.display().to_string()).into());
+    }
+    Ok(())
+}
+
+/// Directory keeping the last `count` reports for debugging
+pub struct Archive {
+    pub dir: PathBuf,
+    pub count: usize,
+}
+
+/// Where AMDuProfPcm writes its reports, from a strftime path template
+pub struct OutputFiles {
+    template: String,
+    max_age: Duration,
+    archive: Option<Archive>,
+    /// File names of reports, with and without the suffix of `path`
+    reports: [String; 2],
+    /// File names of archived reports, see `Archive::store`
+    archived: String,
+}
+
+impl OutputFiles {
+    pub fn new(template: String, max_age: Duration, archive: Option<Archive>) -> Self {
+        let reports = [file_name_glob(&template, ""), file_name_glob(&template, "_*")];
+        let archived = file_name_glob(&template, "*-*");
+        Self { template, max_age, archive, reports, archived }
+    }
+
+    fn is_report(&self, name: &str) -> bool {
+        self.reports.iter().any(|glob| glob::matches(glob, name))
+    }
+
+    /// Report path of a run starting now; runs that overlap pass a `suffix`
+    /// to get their own file next to it
+    pub fn path(&self, suffix: &[String]) -> PathBuf {
+        let path = PathBuf::from(Local::now().format(&self.template).to_string());
+        if suffix.is_empty() {
+            return path;
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(extension) => format!("{}_{}.{}", stem, suffix.join("_"), extension.to_string_lossy()),
+            None => format!("{}_{}", stem, suffix.join("_")),
+        };
+        path.with_file_name(name)
+    }
+
+    /// Archives or deletes a report once read, along with reports of earlier
+    /// runs older than the maximum age that a failed run left behind
+    pub async fn clean_up(&self, report: &Path) {
+        let _ = tokio::fs::remove_file(sidecar(report, "sha256")).await;
+        for extension in METADATA_EXTENSIONS {
+            let _ = tokio::fs::remove_file(sidecar(report, extension)).await;
+        }
+        match &self.archive {
+            Some(archive) => {
+                if let Err(e) = archive.store(report, &self.archived).await {
+                    eprintln!("Failed to archive {}: {}", report.display(), e);
+                    let _ = tokio::fs::remove_file(report).await;
+                }
+            }
+            None => {
+                let _ = tokio::fs::remove_file(report).await;
+            }
+        }
+
+        let template = Path::new(&self.template);
+        let Some(dir) = template.parent() else {
+            return;
+        };
+        let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+            return;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if !self.is_report(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let modified = entry.metadata().await.and_then(|metadata| metadata.modified());
+            let expired = modified.is_ok_and(|modified| {
+                SystemTime::now().duration_since(modified).unwrap_or_default() > self.max_age
+            });
+            if expired {
+                let _ = tokio::fs::remove_file(&path).await;
+            }
+        }
+    }
+}
+
+impl Archive {
+    /// Moves a report into the archive under a timestamped name, then drops
+    /// the oldest archived reports, those matching `archived`, beyond `count`
+    async fn store(&self, report: &Path, archived: &str) -> std::io::Result<()> {
+        let stem = report.file_stem().unwrap_or_default().to_string_lossy();
+        let timestamp = Local::now().format("%Y%m%dT%H%M%S%.3f");
+        let name = match report.extension() {
+            Some(extension) => format!("{}-{}.{}", stem, timestamp, extension.to_string_lossy()),
+            None => format!("{}-{}", stem, timestamp),
+        };
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let destination = self.dir.join(name);
+        // rename(2) does not cross filesystems
+        if tokio::fs::rename(report, &destination).await.is_err() {
+            tokio::fs::copy(report, &destination).await?;
+            tokio::fs::remove_file(report).await?;
+        }
+
+        let mut reports = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if !glob::matches(archived, &entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                reports.push((metadata.modified()?, entry.path()));
+            }
+        }
+        reports.sort();
+        let excess = reports.len().saturating_sub(self.count);
+        for (_, path) in &reports[..excess] {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_templates_cleanup_cannot_match() {
+        assert!(parse_template("/tmp/uprof_%Y%m%d.csv").is_ok());
+        assert!(parse_template("/var/uprof/%Y%m%d.csv").is_err());
+        assert!(parse_template("/var/uprof/%Y/report_%d.csv").is_err());
+        assert!(parse_template("%Y.csv").is_err());
+    }
+
+    #[test]
+    fn matches_only_reports_of_the_template() {
+        let files = OutputFiles::new("/tmp/uprof_%Y%m%d_%H%M%S.csv".to_string(), Duration::ZERO, None);
+        assert!(files.is_report("uprof_20260101_120000.csv"));
+        assert!(files.is_report("uprof_20260101_120000_0_1.csv"));
+        assert!(!files.is_report("uprof_20260101_120000.txt"));
+        assert!(!files.is_report("other_20260101.csv"));
+        assert!(!files.is_report("notes.csv"));
+        assert!(glob::matches(&files.archived, "uprof_20260101_120000-20260101T120002.123.csv"));
+        assert!(!glob::matches(&files.archived, "backup.csv"));
+    }
+}
@@ -0,0 +1,363 @@
+use crate::columns;
+use crate::cpu::ZenGeneration;
+use crate::msr::{self, Direction};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::FromRawFd;
+use std::time::Instant;
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_RAW: u32 = 4;
+const PERF_COUNT_HW_INSTRUCTIONS: u64 = 1;
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+const INSTRUCTIONS: &str = "instructions";
+const L3_ACCESS: &str = "l3_lookup_state.all_coherent_accesses_to_l3";
+const L3_MISS: &str = "l3_lookup_state.l3_miss";
+const DRAM_READ: &str = "dram_read_data_beats";
+const DRAM_WRITE: &str = "dram_write_data_beats";
+const DRAM_TOTAL: &str = "dram_channel_data_beats";
+
+/// Core PMU events of Zen 3/4 behind the uProf L1/L2 metrics; Zen 2 encodes
+/// them differently and is left to AMDuProfPcm
+const CORE_EVENTS: &[(&str, u64)] = &[
+    ("ic_tag_hit_miss.instruction_cache_accesses", raw(0x18E, 0x1F)),
+    ("ic_tag_hit_miss.instruction_cache_miss", raw(0x18E, 0x18)),
+    ("op_cache_hit_miss.all_op_cache_accesses", raw(0x28F, 0x07)),
+    ("op_cache_hit_miss.op_cache_miss", raw(0x28F, 0x04)),
+    ("ls_dispatch.all", raw(0x29, 0x07)),
+    ("l2_request_g1.all_no_prefetch", raw(0x60, 0xF9)),
+    ("l2_request_g1.cacheable_ic_read", raw(0x60, 0x10)),
+    ("l2_request_g1.dc_miss", raw(0x60, 0xE8)),
+    ("l2_cache_req_stat.ic_fill_miss", raw(0x64, 0x01)),
+    ("l2_cache_req_stat.ls_rd_blk_c", raw(0x64, 0x08)),
+    ("l2_cache_req_stat.ic_hit_in_l2", raw(0x64, 0x06)),
+    ("l2_cache_req_stat.dc_hit_in_l2", raw(0x64, 0x70)),
+];
+
+/// L2 hardware prefetcher events, only on Zen 4
+const ZEN4_CORE_EVENTS: &[(&str, u64)] = &[
+    ("l2_pf_hit_l2", raw(0x70, 0xFF)),
+    ("l2_pf_miss_l2_hit_l3", raw(0x71, 0xFF)),
+    ("l2_pf_miss_l2_l3", raw(0x72, 0xFF)),
+];
+
+const fn raw(event: u64, umask: u64) -> u64 {
+    (event & 0xFF) | (umask << 8) | ((event >> 8) & 0xF) << 32
+}
+
+/// `struct perf_event_attr`, PERF_ATTR_SIZE_VER5 layout
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved: u16,
+}
+
+pub(crate) struct Counter {
+    name: &'static str,
+    file: File,
+    last: [u64; 3],
+}
+
+impl Counter {
+    pub(crate) fn open(name: &'static str, pmu: u32, config: u64, cpu: u32) -> io::Result<Self> {
+        let attr = PerfEventAttr {
+            type_: pmu,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            config,
+            read_format: PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING,
+            ..Default::default()
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_perf_event_open,
+                &attr as *const PerfEventAttr,
+                -1 as libc::pid_t,
+                cpu as libc::c_int,
+                -1 as libc::c_int,
+                PERF_FLAG_FD_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::new(
+                io::Error::last_os_error().kind(),
+                format!("perf_event_open({}) on cpu {}: {}", name, cpu, io::Error::last_os_error()),
+            ));
+        }
+        let file = unsafe { File::from_raw_fd(fd as libc::c_int) };
+        Ok(Self { name, file, last: [0; 3] })
+    }
+
+    /// Count since the previous read, scaled up when the kernel had to
+    /// multiplex the counter
+    pub(crate) fn delta(&mut self) -> io::Result<f64> {
+        let mut buf = [0u8; 24];
+        self.file.read_exact(&mut buf)?;
+        let mut current = [0u64; 3];
+        for (value, chunk) in current.iter_mut().zip(buf.chunks_exact(8)) {
+            *value = u64::from_ne_bytes(chunk.try_into().unwrap());
+        }
+
+        let count = current[0].wrapping_sub(self.last[0]) as f64;
+        let enabled = current[1].wrapping_sub(self.last[1]) as f64;
+        let running = current[2].wrapping_sub(self.last[2]) as f64;
+        self.last = current;
+
+        if running == 0.0 {
+            return Ok(0.0);
+        }
+        Ok(count * enabled / running)
+    }
+}
+
+/// Collects the uProf metric set through `perf_event_open(2)` using the
+/// core, `amd_l3` and `amd_df` PMUs.
+pub struct PerfSampler {
+    counters: Vec<Counter>,
+    last: Instant,
+}
+
+impl PerfSampler {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let mut counters = Vec::new();
+        let generation = ZenGeneration::detect();
+        // Raw event numbers mean something else on other generations
+        let zen3_events = matches!(generation, Some(ZenGeneration::Zen3 | ZenGeneration::Zen4));
+        let core_events = match generation {
+            Some(ZenGeneration::Zen4) => [CORE_EVENTS, ZEN4_CORE_EVENTS].concat(),
+            _ if zen3_events => CORE_EVENTS.to_vec(),
+            _ => Vec::new(),
+        };
+
+        for cpu in parse_cpu_list(&fs::read_to_string("/sys/devices/system/cpu/online")?) {
+            counters.push(Counter::open(INSTRUCTIONS, PERF_TYPE_HARDWARE, PERF_COUNT_HW_INSTRUCTIONS, cpu)?);
+            for &(name, config) in &core_events {
+                counters.push(Counter::open(name, PERF_TYPE_RAW, config, cpu)?);
+            }
+        }
+
+        if let Some((pmu, cpus)) = uncore_pmu("amd_l3").filter(|_| zen3_events) {
+            for cpu in cpus {
+                counters.push(Counter::open(L3_ACCESS, pmu, 0x04 | 0xFF << 8, cpu)?);
+                counters.push(Counter::open(L3_MISS, pmu, 0x04 | 0x01 << 8, cpu)?);
+            }
+        }
+
+        if let (Some((pmu, cpus)), Some(generation)) = (uncore_pmu("amd_df"), generation) {
+            let events = msr::df_events(generation);
+            for event in &events {
+                let name = match event.direction {
+                    Direction::Read => DRAM_READ,
+                    Direction::Write => DRAM_WRITE,
+                    Direction::Total => DRAM_TOTAL,
+                };
+                let config = pmu_config("amd_df", &[("event", event.event), ("umask", event.umask)])?;
+                for &cpu in &cpus {
+                    counters.push(Counter::open(name, pmu, config, cpu)?);
+                }
+            }
+        }
+
+        Ok(Self { counters, last: Instant::now() })
+    }
+
+    /// Returns a row laid out like AMDuProfPcm output, with NaN in columns
+    /// that cannot be derived from the available PMUs
+    pub fn sample(&mut self) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+        let elapsed = self.last.elapsed().as_secs_f64();
+        self.last = Instant::now();
+
+        let mut counts: HashMap<&str, f64> = HashMap::new();
+        for counter in &mut self.counters {
+            *counts.entry(counter.name).or_default() += counter.delta()?;
+        }
+
+        Ok(derive(&counts, elapsed))
+    }
+}
+
+fn derive(counts: &HashMap<&str, f64>, elapsed: f64) -> Vec<f64> {
+    let count = |name: &str| counts.get(name).copied().unwrap_or(f64::NAN);
+    let instructions = count(INSTRUCTIONS);
+    let pti = |events: f64| events * 1000.0 / instructions;
+
+    let ic_access = count("ic_tag_hit_miss.instruction_cache_accesses");
+    let ic_miss = count("ic_tag_hit_miss.instruction_cache_miss");
+    let l2_pf_hit_l2 = count("l2_pf_hit_l2");
+    let l2_pf_miss_l2 = count("l2_pf_miss_l2_hit_l3") + count("l2_pf_miss_l2_l3");
+    let l2_access_hwpf = l2_pf_hit_l2 + l2_pf_miss_l2;
+    let l2_miss_ic = count("l2_cache_req_stat.ic_fill_miss");
+    let l2_miss_dc = count("l2_cache_req_stat.ls_rd_blk_c");
+    let l2_hit_ic = count("l2_cache_req_stat.ic_hit_in_l2");
+    let l2_hit_dc = count("l2_cache_req_stat.dc_hit_in_l2");
+
+    let mut values = vec![f64::NAN; columns::COUNT];
+    values[columns::IC_FETCH_MISS_RATIO] = ic_miss / ic_access;
+    values[columns::OP_CACHE_FETCH_MISS_RATIO] =
+        count("op_cache_hit_miss.op_cache_miss") / count("op_cache_hit_miss.all_op_cache_accesses");
+    values[columns::IC_ACCESS_PTI] = pti(ic_access);
+    values[columns::IC_MISS_PTI] = pti(ic_miss);
+    values[columns::DC_ACCESS_PTI] = pti(count("ls_dispatch.all"));
+    values[columns::L2_ACCESS_FROM_IC_MISS_PTI] = pti(count("l2_request_g1.cacheable_ic_read"));
+    values[columns::L2_ACCESS_FROM_DC_MISS_PTI] = pti(count("l2_request_g1.dc_miss"));
+    values[columns::L2_MISS_FROM_IC_MISS_PTI] = pti(l2_miss_ic);
+    values[columns::L2_MISS_FROM_DC_MISS_PTI] = pti(l2_miss_dc);
+    values[columns::L2_HIT_FROM_IC_MISS_PTI] = pti(l2_hit_ic);
+    values[columns::L2_HIT_FROM_DC_MISS_PTI] = pti(l2_hit_dc);
+    // Prefetcher events are only opened on Zen 4; without them the totals
+    // they are part of are not reported either
+    if counts.contains_key("l2_pf_hit_l2") {
+        values[columns::L2_ACCESS_PTI] = pti(count("l2_request_g1.all_no_prefetch") + l2_access_hwpf);
+        values[columns::L2_ACCESS_FROM_L2_HWPF_PTI] = pti(l2_access_hwpf);
+        values[columns::L2_MISS_PTI] = pti(l2_miss_ic + l2_miss_dc + l2_pf_miss_l2);
+        values[columns::L2_MISS_FROM_L2_HWPF_PTI] = pti(l2_pf_miss_l2);
+        values[columns::L2_HIT_PTI] = pti(l2_hit_ic + l2_hit_dc + l2_pf_hit_l2);
+        values[columns::L2_HIT_FROM_L2_HWPF_PTI] = pti(l2_pf_hit_l2);
+    }
+
+    let l3_access = count(L3_ACCESS);
+    let l3_miss = count(L3_MISS);
+    values[columns::L3_ACCESS] = l3_access / elapsed;
+    values[columns::L3_MISS] = l3_miss / elapsed;
+    if l3_access > 0.0 {
+        values[columns::L3_MISS_PERCENT] = l3_miss * 100.0 / l3_access;
+        values[columns::L3_HIT_PERCENT] = 100.0 - values[columns::L3_MISS_PERCENT];
+    }
+
+    let gbps = |beats: f64| beats * msr::BYTES_PER_BEAT / elapsed / 1e9;
+    let read = gbps(count(DRAM_READ));
+    let write = gbps(count(DRAM_WRITE));
+    values[columns::TOTAL_MEM_RDBW_GBPS] = read;
+    values[columns::TOTAL_MEM_WRBW_GBPS] = write;
+    values[columns::TOTAL_MEM_BW_GBPS] = if counts.contains_key(DRAM_TOTAL) {
+        gbps(count(DRAM_TOTAL))
+    } else {
+        read + write
+    };
+
+    values
+}
+
+/// Dynamic PMU type and the CPUs its events have to be opened on
+pub(crate) fn uncore_pmu(name: &str) -> Option<(u32, Vec<u32>)> {
+    let dir = format!("/sys/bus/event_source/devices/{}", name);
+    let pmu = fs::read_to_string(format!("{}/type", dir)).ok()?.trim().parse().ok()?;
+    let cpus = parse_cpu_list(&fs::read_to_string(format!("{}/cpumask", dir)).ok()?);
+    Some((pmu, cpus))
+}
+
+/// Packs `fields` into a `config` value the way the dynamic PMU's sysfs
+/// `format` files lay them out
+pub(crate) fn pmu_config(pmu: &str, fields: &[(&str, u64)]) -> io::Result<u64> {
+    let mut config = 0;
+    for &(field, value) in fields {
+        let format = fs::read_to_string(format!("/sys/bus/event_source/devices/{}/format/{}", pmu, field))?;
+        config |= encode_field(&format, value).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{} {:#x} of {}: {}", field, value, pmu, e))
+        })?;
+    }
+    Ok(config)
+}
+
+/// Spreads `value` over the bits of a format such as `config:0-7,32-37`,
+/// its lowest bits into the first range
+fn encode_field(format: &str, value: u64) -> Result<u64, String> {
+    let unsupported = || format!("unsupported format {:?}", format.trim());
+    let ranges = format.trim().strip_prefix("config:").ok_or_else(unsupported)?;
+    let mut config = 0;
+    let mut rest = value;
+    for range in ranges.split(',') {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let (start, end): (u32, u32) = (start.parse().map_err(|_| unsupported())?, end.parse().map_err(|_| unsupported())?);
+        if start > end || end > 63 {
+            return Err(unsupported());
+        }
+        let width = end - start + 1;
+        let mask = u64::MAX >> (64 - width);
+        config |= (rest & mask) << start;
+        rest = rest.checked_shr(width).unwrap_or(0);
+    }
+    if rest != 0 {
+        return Err("does not fit".to_string());
+    }
+    Ok(config)
+}
+
+/// Parses kernel CPU lists such as `0-3,8,10-11`
+fn parse_cpu_list(list: &str) -> Vec<u32> {
+    list.trim()
+        .split(',')
+        .filter_map(|range| match range.split_once('-') {
+            Some((start, end)) => Some(start.parse().ok()?..=end.parse().ok()?),
+            None => {
+                let cpu = range.parse().ok()?;
+                Some(cpu..=cpu)
+            }
+        })
+        .flatten()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_prefetch_columns_out_without_prefetch_events() {
+        let counts: HashMap<&str, f64> = [
+            (INSTRUCTIONS, 1000.0),
+            ("ic_tag_hit_miss.instruction_cache_accesses", 100.0),
+            ("ic_tag_hit_miss.instruction_cache_miss", 10.0),
+            ("l2_request_g1.all_no_prefetch", 50.0),
+            ("l2_cache_req_stat.ic_fill_miss", 1.0),
+            ("l2_cache_req_stat.ls_rd_blk_c", 2.0),
+            ("l2_cache_req_stat.ic_hit_in_l2", 3.0),
+            ("l2_cache_req_stat.dc_hit_in_l2", 4.0),
+        ]
+        .into_iter()
+        .collect();
+        let values = derive(&counts, 1.0);
+        assert_eq!(values[columns::IC_FETCH_MISS_RATIO], 0.1);
+        assert_eq!(values[columns::L2_MISS_FROM_DC_MISS_PTI], 2.0);
+        for column in [
+            columns::L2_ACCESS_PTI,
+            columns::L2_ACCESS_FROM_L2_HWPF_PTI,
+            columns::L2_MISS_PTI,
+            columns::L2_MISS_FROM_L2_HWPF_PTI,
+            columns::L2_HIT_PTI,
+            columns::L2_HIT_FROM_L2_HWPF_PTI,
+        ] {
+            assert!(values[column].is_nan(), "column {}", column);
+        }
+    }
+
+    #[test]
+    fn encodes_amd_df_fields() {
+        assert_eq!(encode_field("config:0-7,32-37\n", 0x1C7), Ok(0xC7 | 0x1 << 32));
+        assert_eq!(encode_field("config:8-15,24-27\n", 0x7FE), Ok(0xFE << 8 | 0x7 << 24));
+        assert_eq!(encode_field("config:8-15\n", 0x38), Ok(0x38 << 8));
+        assert!(encode_field("config:8-15\n", 0x7FE).is_err());
+        assert!(encode_field("config1:0-7\n", 1).is_err());
+    }
+}
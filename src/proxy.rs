@@ -0,0 +1,124 @@
+use hyper::HeaderMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An address block such as `10.0.0.0/8` or `fd00::/8`
+#[derive(Clone, Copy, Debug)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl Cidr {
+    /// IPv4-mapped IPv6 addresses, as dual-stack listeners see IPv4 peers,
+    /// count as the IPv4 address
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// A bare address is a block of one
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = s.split_once('/').unwrap_or((s, ""));
+        let network: IpAddr = address.parse().map_err(|e| format!("{}: {}", address, e))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = if prefix.is_empty() {
+            max
+        } else {
+            prefix.parse().map_err(|_| format!("invalid prefix length in {}", s))?
+        };
+        if prefix > max {
+            return Err(format!("prefix length of {} exceeds {}", s, max));
+        }
+        Ok(Self { network, prefix })
+    }
+}
+
+/// Address of the client behind trusted reverse proxies.
+///
+/// `X-Forwarded-For` is only believed when the connection comes from a
+/// trusted proxy; its entries are walked from the right and the first one
+/// that is not a trusted proxy itself is the client.
+pub fn client_ip(headers: &HeaderMap, peer: IpAddr, trusted: &[Cidr]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|entry| entry.trim().parse().ok())
+        .collect();
+    forwarded
+        .iter()
+        .rev()
+        .find(|ip| !is_trusted(**ip))
+        .or(forwarded.first())
+        .copied()
+        .unwrap_or(peer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_prefix_lengths() {
+        assert!(cidr("0.0.0.0/0").contains(ip("203.0.113.7")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+        assert!(cidr("10.1.2.3/32").contains(ip("10.1.2.3")));
+        assert!(!cidr("10.1.2.3/32").contains(ip("10.1.2.4")));
+        assert!(cidr("10.1.2.3").contains(ip("10.1.2.3")));
+        assert!(cidr("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert!(!cidr("2001:db8::1/128").contains(ip("2001:db8::2")));
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.9.8.7")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::ffff:11.0.0.1")));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("2001:db8::/129".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn walks_forwarded_chain_from_the_right() {
+        let trusted = [cidr("10.0.0.0/8")];
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Forwarded-For", HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        let chain = headers("198.51.100.1, 203.0.113.9, 10.0.0.2");
+        // The untrusted hop next to the proxies, not the spoofable leftmost entry
+        assert_eq!(client_ip(&chain, ip("10.0.0.1"), &trusted), ip("203.0.113.9"));
+        assert_eq!(client_ip(&chain, ip("::ffff:10.0.0.1"), &trusted), ip("203.0.113.9"));
+        // The header of an untrusted peer is ignored
+        assert_eq!(client_ip(&chain, ip("192.0.2.1"), &trusted), ip("192.0.2.1"));
+        // Proxies all the way down leave the leftmost entry
+        assert_eq!(client_ip(&headers("10.0.0.3, 10.0.0.2"), ip("10.0.0.1"), &trusted), ip("10.0.0.3"));
+        assert_eq!(client_ip(&HeaderMap::new(), ip("10.0.0.1"), &trusted), ip("10.0.0.1"));
+    }
+}
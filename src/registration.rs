@@ -0,0 +1,107 @@
+use prometheus::core::Collector;
+use prometheus::Registry;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+
+/// A metric the registry refused, e.g. because its name is already taken
+#[derive(Debug)]
+pub struct RegistrationError {
+    pub metric: String,
+    pub error: prometheus::Error,
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.metric, self.error)
+    }
+}
+
+/// Registry handed to collectors at startup; every failed registration is
+/// kept so that all of them can be reported together
+pub struct Registrar {
+    registry: Registry,
+    errors: RefCell<Vec<RegistrationError>>,
+    /// Names taken in any registry served together with this one
+    names: RefCell<HashSet<String>>,
+}
+
+impl Registrar {
+    pub fn new(registry: Registry) -> Self {
+        Self {
+            registry,
+            errors: RefCell::new(Vec::new()),
+            names: RefCell::new(HashSet::new()),
+        }
+    }
+
+    pub fn register<C: Collector + Clone + 'static>(&self, metric: &C) {
+        self.register_in(&self.registry, metric);
+    }
+
+    /// Registers a clone of `metric` in another registry whose metrics are
+    /// served along with this one's, such as a metric group's
+    pub fn register_in<C: Collector + Clone + 'static>(&self, registry: &Registry, metric: &C) {
+        if !self.claim(metric) {
+            return;
+        }
+        if let Err(error) = registry.register(Box::new(metric.clone())) {
+            self.fail(metric, error);
+        }
+    }
+
+    /// Reserves the names of a metric registered later, returning false
+    /// when one of them is taken already
+    pub fn claim<C: Collector>(&self, metric: &C) -> bool {
+        let taken = {
+            let mut names = self.names.borrow_mut();
+            let mut descs = metric.desc().into_iter();
+            descs.find(|desc| !names.insert(desc.fq_name.clone())).map(|desc| desc.fq_name.clone())
+        };
+        match taken {
+            Some(name) => {
+                let error = prometheus::Error::Msg(format!("name {} is already taken by another metric", name));
+                self.fail(metric, error);
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn fail<C: Collector>(&self, metric: &C, error: prometheus::Error) {
+        let metric = metric.desc().first().map_or_else(String::new, |desc| desc.fq_name.clone());
+        self.errors.borrow_mut().push(RegistrationError { metric, error });
+    }
+
+    /// Runs `register`, taking out the errors of the registrations it made;
+    /// the names it claimed stay taken
+    pub fn scoped<T>(&self, register: impl FnOnce(&Self) -> T) -> Result<T, Vec<RegistrationError>> {
+        let start = self.errors.borrow().len();
+        let value = register(self);
+        let errors: Vec<_> = self.errors.borrow_mut().drain(start..).collect();
+        if errors.is_empty() {
+            Ok(value)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The registry, or the errors of every registration that failed
+    pub fn finish(self) -> Result<Registry, Vec<RegistrationError>> {
+        let errors = self.errors.into_inner();
+        if errors.is_empty() {
+            Ok(self.registry)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Prints every error and exits
+pub fn fail(errors: &[RegistrationError]) -> ! {
+    eprintln!("Failed to register {} metric(s):", errors.len());
+    for error in errors {
+        eprintln!("  {}", error);
+    }
+    std::process::exit(1);
+}
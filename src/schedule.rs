@@ -0,0 +1,132 @@
+use chrono::{DateTime, Local, TimeZone};
+use cron::error::{Error, ErrorKind};
+use cron::Schedule;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// Time window in which collection runs, from a `--schedule` cron expression.
+///
+/// Five-field crontab expressions (`*/5 9-17 * * 1-5`) select whole minutes
+/// with crontab day-of-week numbering, and like crontab match when either
+/// the day of month or the day of week does if both are restricted; six and
+/// seven-field expressions are passed to the `cron` crate as they are,
+/// seconds first.
+pub struct CollectionSchedule {
+    /// Active when any of them includes the current time
+    schedules: Vec<Schedule>,
+}
+
+impl CollectionSchedule {
+    pub fn parse(expression: &str) -> Result<Self, Error> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let expressions = match fields.as_slice() {
+            [minute, hour, day, month, weekday] => {
+                let crontab = |day: &str, weekdays: &str| format!("* {} {} {} {} {}", minute, hour, day, month, weekdays);
+                let weekdays = crontab_weekdays(weekday)?;
+                if day.starts_with('*') || weekday.starts_with('*') {
+                    vec![crontab(day, &weekdays)]
+                } else {
+                    vec![crontab(day, "*"), crontab("*", &weekdays)]
+                }
+            }
+            _ => vec![expression.to_string()],
+        };
+        let schedules = expressions
+            .iter()
+            .map(|expression| Schedule::from_str(expression))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { schedules })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.includes(Local::now())
+    }
+
+    fn includes<Z: TimeZone>(&self, time: DateTime<Z>) -> bool {
+        self.schedules.iter().any(|schedule| schedule.includes(time.clone()))
+    }
+}
+
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Crontab day of week, 0-7 with both 0 and 7 for Sunday, or a name
+fn crontab_weekday(day: &str) -> Result<u32, Error> {
+    let lowercase = day.to_ascii_lowercase();
+    match WEEKDAY_NAMES.iter().position(|name| *name == lowercase) {
+        Some(day) => Ok(day as u32),
+        None => day
+            .parse()
+            .ok()
+            .filter(|day| *day <= 7)
+            .ok_or_else(|| ErrorKind::Expression(format!("invalid day of week {:?}", day)).into()),
+    }
+}
+
+/// Crontab counts weekdays from Sunday = 0 (or 7), the `cron` crate from
+/// Sunday = 1, so the field is expanded into a list of `cron` days; a range
+/// such as `5-7` would otherwise wrap around the week
+fn crontab_weekdays(field: &str) -> Result<String, Error> {
+    if field == "*" || field == "?" {
+        return Ok(field.to_string());
+    }
+    let mut days = BTreeSet::new();
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step = step.parse().ok().filter(|step| *step > 0);
+                (range, Some(step.ok_or_else(|| ErrorKind::Expression(format!("invalid step in {:?}", item)))?))
+            }
+            None => (item, None),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (0, 6),
+            Some((first, last)) => (crontab_weekday(first)?, crontab_weekday(last)?),
+            // `5/2` runs from 5 to the end of the week
+            None if step.is_some() => (crontab_weekday(range)?, 7),
+            None => {
+                let day = crontab_weekday(range)?;
+                (day, day)
+            }
+        };
+        if first > last {
+            return Err(ErrorKind::Expression(format!("invalid range of days of week {:?}", item)).into());
+        }
+        days.extend((first..=last).step_by(step.unwrap_or(1)).map(|day| day % 7));
+    }
+    Ok(days.iter().map(|day| (day + 1).to_string()).collect::<Vec<_>>().join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn converts_crontab_weekdays() {
+        assert_eq!(crontab_weekdays("0").unwrap(), "1");
+        assert_eq!(crontab_weekdays("7").unwrap(), "1");
+        assert_eq!(crontab_weekdays("1-7").unwrap(), "1,2,3,4,5,6,7");
+        assert_eq!(crontab_weekdays("5-7").unwrap(), "1,6,7");
+        assert_eq!(crontab_weekdays("1,3,5").unwrap(), "2,4,6");
+        assert_eq!(crontab_weekdays("sun,Sat").unwrap(), "1,7");
+        assert_eq!(crontab_weekdays("*/2").unwrap(), "1,3,5,7");
+        assert_eq!(crontab_weekdays("1-5/2").unwrap(), "2,4,6");
+        assert_eq!(crontab_weekdays("3/2").unwrap(), "1,4,6");
+        assert!(crontab_weekdays("8").is_err());
+        assert!(crontab_weekdays("5-1").is_err());
+        assert!(crontab_weekdays("*/0").is_err());
+    }
+
+    #[test]
+    fn matches_either_day_field_like_crontab() {
+        // 2024-06-01 is a Saturday, 2024-06-03 a Monday
+        let saturday_first = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let monday_third = Utc.with_ymd_and_hms(2024, 6, 3, 12, 0, 0).unwrap();
+        let either = CollectionSchedule::parse("* * 1 * 1").unwrap();
+        assert!(either.includes(saturday_first));
+        assert!(either.includes(monday_third));
+        let weekdays_only = CollectionSchedule::parse("* * * * 1-5").unwrap();
+        assert!(!weekdays_only.includes(saturday_first));
+        assert!(weekdays_only.includes(monday_third));
+    }
+}
@@ -0,0 +1,389 @@
+use crate::collectors::read_key_values;
+use crate::health::Readiness;
+use crate::registration::Registrar;
+use prometheus::{Encoder, Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio::time;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The exporter's own metrics, kept apart from the hardware metrics and
+/// served at `/metrics/self`
+pub struct SelfRegistry {
+    registry: Registry,
+}
+
+impl SelfRegistry {
+    pub fn new(registry: Registry) -> Self {
+        Self { registry }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        buffer
+    }
+}
+
+/// Registers the process start time and the build info, both set only once
+pub fn register_process_info(registry: &Registrar) {
+    let start_time = Gauge::new(
+        "amd_uprof_exporter_start_time_seconds",
+        "Start time of the exporter since unix epoch in seconds"
+    ).unwrap();
+    let build_info = GaugeVec::new(
+        Opts::new("amd_uprof_exporter_build_info", "Exporter build information"),
+        &["version", "rustc_version", "target"]
+    ).unwrap();
+
+    registry.register(&start_time);
+    registry.register(&build_info);
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    start_time.set(now.as_secs_f64());
+    build_info
+        .with_label_values(&[env!("CARGO_PKG_VERSION"), env!("RUSTC_VERSION"), env!("TARGET")])
+        .set(1.0);
+}
+
+/// Memory and runtime usage of the exporter process itself
+pub struct SelfMetrics {
+    nodename: String,
+    memory_rss: GaugeVec,
+    memory_peak: GaugeVec,
+    tasks_alive: GaugeVec,
+}
+
+impl SelfMetrics {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let memory_rss = GaugeVec::new(
+            Opts::new("amd_exporter_memory_rss_bytes", "Resident memory of the exporter"),
+            &["nodename"]
+        ).unwrap();
+        let memory_peak = GaugeVec::new(
+            Opts::new("amd_exporter_memory_peak_bytes", "Peak virtual memory of the exporter"),
+            &["nodename"]
+        ).unwrap();
+        let tasks_alive = GaugeVec::new(
+            Opts::new("amd_exporter_tokio_tasks_alive", "Alive tokio tasks"),
+            &["nodename"]
+        ).unwrap();
+
+        registry.register(&memory_rss);
+        registry.register(&memory_peak);
+        registry.register(&tasks_alive);
+
+        Self {
+            nodename: nodename.to_string(),
+            memory_rss,
+            memory_peak,
+            tasks_alive,
+        }
+    }
+
+    fn update(&self) {
+        if let Ok(status) = read_key_values("/proc/self/status") {
+            if let Some(rss) = status.get("VmRSS") {
+                self.memory_rss.with_label_values(&[&self.nodename]).set(rss * 1024.0);
+            }
+            if let Some(peak) = status.get("VmPeak") {
+                self.memory_peak.with_label_values(&[&self.nodename]).set(peak * 1024.0);
+            }
+        }
+
+        let runtime = Handle::current().metrics();
+        self.tasks_alive
+            .with_label_values(&[&self.nodename])
+            .set(runtime.num_alive_tasks() as f64);
+    }
+
+    pub async fn run(self) {
+        let mut interval = time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.update();
+        }
+    }
+}
+
+/// Context switches of all the exporter's threads, updated by the
+/// collection loop so that they show what collecting costs
+pub struct ContextSwitches {
+    nodename: String,
+    voluntary: IntCounterVec,
+    nonvoluntary: IntCounterVec,
+    /// Counts of each thread at the previous update; `/proc/self/status`
+    /// only has the main thread's
+    last: HashMap<String, (u64, u64)>,
+}
+
+impl ContextSwitches {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let voluntary = IntCounterVec::new(
+            Opts::new("amd_voluntary_ctxt_switches_total", "Times the exporter's threads gave up the CPU"),
+            &["nodename"]
+        ).unwrap();
+        let nonvoluntary = IntCounterVec::new(
+            Opts::new("amd_nonvoluntary_ctxt_switches_total", "Times the exporter's threads were preempted"),
+            &["nodename"]
+        ).unwrap();
+        registry.register(&voluntary);
+        registry.register(&nonvoluntary);
+        Self {
+            nodename: nodename.to_string(),
+            voluntary,
+            nonvoluntary,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Adds what every thread did since the previous update; switches of
+    /// threads that exited in between are lost
+    pub fn update(&mut self) {
+        let mut current = HashMap::new();
+        let (mut voluntary, mut nonvoluntary) = (0, 0);
+        for entry in fs::read_dir("/proc/self/task").into_iter().flatten().flatten() {
+            let Ok(status) = read_key_values(entry.path().join("status")) else {
+                continue;
+            };
+            let count = |key: &str| status.get(key).map_or(0, |count| *count as u64);
+            let counts = (count("voluntary_ctxt_switches"), count("nonvoluntary_ctxt_switches"));
+            let tid = entry.file_name().to_string_lossy().into_owned();
+            let last = self.last.get(&tid).copied().unwrap_or_default();
+            voluntary += counts.0.saturating_sub(last.0);
+            nonvoluntary += counts.1.saturating_sub(last.1);
+            current.insert(tid, counts);
+        }
+        self.last = current;
+        self.voluntary.with_label_values(&[&self.nodename]).inc_by(voluntary);
+        self.nonvoluntary.with_label_values(&[&self.nodename]).inc_by(nonvoluntary);
+    }
+}
+
+/// Outcome counters of the uProf collection loop
+pub struct CollectionMetrics {
+    nodename: String,
+    total: GaugeVec,
+    success: GaugeVec,
+    consecutive_failures: GaugeVec,
+    restored: GaugeVec,
+    restored_timestamp: GaugeVec,
+    delay: GaugeVec,
+    watchdog: GaugeVec,
+}
+
+impl CollectionMetrics {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let total = GaugeVec::new(
+            Opts::new("amd_uprof_collections_total", "Collections attempted"),
+            &["nodename"]
+        ).unwrap();
+        let success = GaugeVec::new(
+            Opts::new("amd_uprof_collections_success_total", "Collections that succeeded"),
+            &["nodename"]
+        ).unwrap();
+        let consecutive_failures = GaugeVec::new(
+            Opts::new("amd_uprof_consecutive_failures", "Collections failed in a row since the last success"),
+            &["nodename"]
+        ).unwrap();
+
+        let restored = GaugeVec::new(
+            Opts::new("amd_uprof_restored_from_snapshot", "1 while the uProf values come from --restore-snapshot"),
+            &["nodename"]
+        ).unwrap();
+        let restored_timestamp = GaugeVec::new(
+            Opts::new(
+                "amd_uprof_restored_snapshot_timestamp_seconds",
+                "Collection time of the restored snapshot since unix epoch in seconds"
+            ),
+            &["nodename"]
+        ).unwrap();
+
+        // A throttled container wakes the collection loop late
+        let delay = GaugeVec::new(
+            Opts::new(
+                "amd_uprof_collection_delay_seconds",
+                "How late the last scheduled collection started, compare with amd_container_cpu_throttled_usec_total"
+            ),
+            &["nodename"]
+        ).unwrap();
+
+        let watchdog = GaugeVec::new(
+            Opts::new("amd_uprof_watchdog", "1 after a successful collection, 0 once none succeeded within the watchdog deadline"),
+            &["nodename"]
+        ).unwrap();
+
+        registry.register(&total);
+        registry.register(&success);
+        registry.register(&consecutive_failures);
+        registry.register(&restored);
+        registry.register(&restored_timestamp);
+        registry.register(&delay);
+        registry.register(&watchdog);
+
+        // Export zeros before the first collection finishes
+        for gauge in [&total, &success, &consecutive_failures, &restored, &watchdog] {
+            gauge.with_label_values(&[nodename]).set(0.0);
+        }
+
+        Self {
+            nodename: nodename.to_string(),
+            total,
+            success,
+            consecutive_failures,
+            restored,
+            restored_timestamp,
+            delay,
+            watchdog,
+        }
+    }
+
+    pub fn watchdog(&self) -> Watchdog {
+        Watchdog {
+            nodename: self.nodename.clone(),
+            gauge: self.watchdog.clone(),
+        }
+    }
+
+    pub fn record_delay(&self, delay: Duration) {
+        self.delay.with_label_values(&[&self.nodename]).set(delay.as_secs_f64());
+    }
+
+    pub fn record_restore(&self, collected_at: Option<f64>) {
+        self.restored.with_label_values(&[&self.nodename]).set(1.0);
+        if let Some(collected_at) = collected_at {
+            self.restored_timestamp.with_label_values(&[&self.nodename]).set(collected_at);
+        }
+    }
+
+    pub fn record(&self, succeeded: bool) {
+        self.total.with_label_values(&[&self.nodename]).inc();
+        let failures = self.consecutive_failures.with_label_values(&[&self.nodename]);
+        if succeeded {
+            self.success.with_label_values(&[&self.nodename]).inc();
+            self.restored.with_label_values(&[&self.nodename]).set(0.0);
+            self.watchdog.with_label_values(&[&self.nodename]).set(1.0);
+            failures.set(0.0);
+        } else {
+            failures.inc();
+        }
+    }
+}
+
+/// Resets `amd_uprof_watchdog` when the collection loop stops succeeding,
+/// e.g. because it is stuck on a hung AMDuProfPcm
+pub struct Watchdog {
+    nodename: String,
+    gauge: GaugeVec,
+}
+
+impl Watchdog {
+    /// Checks every `deadline` whether a collection succeeded within it;
+    /// outside the `--schedule` window no collections are expected
+    pub async fn run(
+        self,
+        deadline: Duration,
+        last_collection: Arc<Mutex<Option<Instant>>>,
+        readiness: Arc<Readiness>,
+    ) {
+        let mut interval = time::interval_at(time::Instant::now() + deadline, deadline);
+        loop {
+            interval.tick().await;
+            let last = *last_collection.lock().unwrap();
+            if !readiness.is_idle() && last.is_none_or(|last| last.elapsed() >= deadline) {
+                self.gauge.with_label_values(&[&self.nodename]).set(0.0);
+            }
+        }
+    }
+}
+
+/// Aborts the exporter when the collection loop stops sending heartbeats,
+/// so that the init system restarts it
+pub struct DeadlockDetector {
+    nodename: String,
+    detected: IntCounterVec,
+}
+
+impl DeadlockDetector {
+    pub fn new(registry: &Registrar, nodename: &str) -> Self {
+        let detected = IntCounterVec::new(
+            Opts::new("amd_uprof_collector_deadlock_detected", "Times the collection loop missed its heartbeat"),
+            &["nodename"]
+        ).unwrap();
+        registry.register(&detected);
+        detected.with_label_values(&[nodename]).reset();
+        Self {
+            nodename: nodename.to_string(),
+            detected,
+        }
+    }
+
+    pub async fn run(self, deadline: Duration, mut heartbeats: mpsc::Receiver<()>) {
+        loop {
+            match time::timeout(deadline, heartbeats.recv()).await {
+                Ok(Some(())) => {}
+                // The collection loop is gone, nothing left to watch
+                Ok(None) => return,
+                Err(_) => {
+                    // Seen only by a scrape racing the abort; the log line below is the record
+                    self.detected.with_label_values(&[&self.nodename]).inc();
+                    eprintln!(
+                        "CRITICAL: no heartbeat from the collection loop for {}s, it is deadlocked or stuck; aborting",
+                        deadline.as_secs()
+                    );
+                    std::process::abort();
+                }
+            }
+        }
+    }
+}
+
+/// Latency and response sizes of the HTTP endpoints
+pub struct HttpMetrics {
+    request_duration: HistogramVec,
+    response_size: HistogramVec,
+}
+
+impl HttpMetrics {
+    pub fn new(registry: &Registrar) -> Self {
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("amd_uprof_http_request_duration_seconds", "Time to serve an HTTP request"),
+            &["path", "status"]
+        ).unwrap();
+
+        // Roughly half-decade steps from 1 KB to 10 MB, Prometheus' default body size limit
+        let response_size = HistogramVec::new(
+            HistogramOpts::new("amd_uprof_http_response_size_bytes", "Size of HTTP response bodies")
+                .buckets(vec![1e3, 3e3, 1e4, 3e4, 1e5, 3e5, 1e6, 3e6, 1e7]),
+            &["path"]
+        ).unwrap();
+
+        registry.register(&request_duration);
+        registry.register(&response_size);
+
+        Self { request_duration, response_size }
+    }
+
+    /// Unknown paths share one label so scanners cannot blow up cardinality
+    pub fn record(&self, path: &str, status: u16, duration: Duration, size: Option<u64>) {
+        self.request_duration
+            .with_label_values(&[path_label(path), &status.to_string()])
+            .observe(duration.as_secs_f64());
+        if let Some(size) = size {
+            self.response_size.with_label_values(&[path_label(path)]).observe(size as f64);
+        }
+    }
+}
+
+fn path_label(path: &str) -> &str {
+    match path {
+        "/" | "/metrics" | "/metrics/live" | "/metrics/cached" | "/metrics/stale" | "/metrics/self" | "/readyz"
+        | "/metrics/diff" | "/admin/groups" | "/robots.txt" | "/snapshot" => path,
+        _ => "other",
+    }
+}
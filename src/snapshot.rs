@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Every exported uProf gauge value at one point in time
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct MetricsSnapshot {
+    pub nodename: String,
+    /// Unix time in seconds of the collection the values come from, `None`
+    /// before the first one
+    pub collected_at: Option<f64>,
+    /// Values as exported, keyed by metric name
+    pub values: BTreeMap<String, f64>,
+}
+
+impl MetricsSnapshot {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Replaces the file at `path` without readers ever seeing half of it
+    pub async fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        tokio::fs::write(&temporary, serde_json::to_vec_pretty(self)?).await?;
+        tokio::fs::rename(&temporary, path).await
+    }
+
+    /// Prometheus text of the values that moved by more than
+    /// `threshold_percent` since `previous`, each after a comment with the
+    /// change; returns the text and the number of unchanged values
+    pub fn diff(&self, previous: &MetricsSnapshot, threshold_percent: f64) -> (String, usize) {
+        let mut text = String::new();
+        let mut unchanged = 0;
+        for (name, &value) in &self.values {
+            let comment = match previous.values.get(name) {
+                None => "# new".to_string(),
+                Some(&before) => {
+                    let delta = value - before;
+                    let percent = delta / before.abs() * 100.0;
+                    // From zero any change is infinitely large, no change is NaN
+                    if delta == 0.0 || percent.abs() <= threshold_percent {
+                        unchanged += 1;
+                        continue;
+                    }
+                    format!("# delta {:+} ({:+.2}%)", delta, percent)
+                }
+            };
+            let _ = writeln!(text, "# TYPE {} gauge", name);
+            let _ = writeln!(text, "{}", comment);
+            let _ = writeln!(text, "{}{{nodename=\"{}\"}} {}", name, self.nodename, value);
+        }
+        (text, unchanged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(values: &[(&str, f64)]) -> MetricsSnapshot {
+        MetricsSnapshot {
+            nodename: "node".to_string(),
+            collected_at: None,
+            values: values.iter().map(|&(name, value)| (name.to_string(), value)).collect(),
+        }
+    }
+
+    #[test]
+    fn keeps_only_changes_above_threshold() {
+        let previous = snapshot(&[("a", 100.0), ("b", 100.0), ("c", 0.0)]);
+        let current = snapshot(&[("a", 100.5), ("b", 110.0), ("c", 0.0), ("d", 1.0)]);
+        let (text, unchanged) = current.diff(&previous, 1.0);
+        assert_eq!(unchanged, 2);
+        assert_eq!(
+            text,
+            "# TYPE b gauge\n# delta +10 (+10.00%)\nb{nodename=\"node\"} 110\n\
+             # TYPE d gauge\n# new\nd{nodename=\"node\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn reports_changes_from_zero() {
+        let (text, unchanged) = snapshot(&[("a", 2.0)]).diff(&snapshot(&[("a", 0.0)]), 1.0);
+        assert_eq!(unchanged, 0);
+        assert!(text.contains("# delta +2 (+inf%)"), "{}", text);
+    }
+}
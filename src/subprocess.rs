@@ -0,0 +1,130 @@
+use nix::errno::Errno;
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::io;
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::{self, Instant};
+
+/// Runs taking longer are killed; well under the deadline of the deadlock
+/// detector, so that a slow AMDuProfPcm fails one collection instead of
+/// aborting the exporter
+pub const TIMEOUT: Duration = Duration::from_secs(4);
+
+/// A command, where to send the pid of its process once started, and where
+/// to send its output
+type Job = (Command, oneshot::Sender<u32>, oneshot::Sender<io::Result<Output>>);
+
+/// Runs external tools on a dedicated OS thread, so waiting for them never
+/// ties up the runtime that serves HTTP
+pub struct SubprocessThread {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl SubprocessThread {
+    /// Children inherit the thread's affinity and scheduling policy, so
+    /// pinning it to `cpus` keeps AMDuProfPcm on the same NUMA domain and
+    /// `rt_priority` runs both under SCHED_FIFO
+    pub fn spawn(cpus: &[usize], rt_priority: Option<i32>) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let cpus = cpus.to_vec();
+        thread::Builder::new()
+            .name("subprocess".to_string())
+            .spawn(move || {
+                if !cpus.is_empty() {
+                    if let Err(e) = pin_current_thread(&cpus) {
+                        eprintln!("Failed to set collector CPU affinity: {}", e);
+                    }
+                }
+                if let Some(priority) = rt_priority {
+                    if let Err(e) = set_fifo_priority(priority) {
+                        eprintln!("Failed to set SCHED_FIFO priority {}: {}", priority, e);
+                    }
+                }
+                for (mut command, started, reply) in queue {
+                    let child = command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn();
+                    let result = child.and_then(|mut child| {
+                        // Nobody waits any more, the job timed out in the queue
+                        if started.send(child.id()).is_err() {
+                            let _ = child.kill();
+                        }
+                        child.wait_with_output()
+                    });
+                    let _ = reply.send(result);
+                }
+            })
+            .expect("Failed to spawn subprocess thread");
+        Self { jobs }
+    }
+
+    async fn output(&self, command: Command) -> io::Result<Output> {
+        let deadline = Instant::now() + TIMEOUT;
+        let program = command.get_program().to_string_lossy().into_owned();
+        let (started, pid) = oneshot::channel();
+        let (reply, result) = oneshot::channel();
+        let closed = || io::Error::other("subprocess thread exited");
+        self.jobs.send((command, started, reply)).map_err(|_| closed())?;
+        // A failed spawn drops the pid sender and still replies
+        let pid = time::timeout_at(deadline, pid).await.map_err(|_| timed_out(&program))?.ok();
+        match time::timeout_at(deadline, result).await {
+            Ok(result) => result.map_err(|_| closed())?,
+            Err(_) => {
+                if let Some(pid) = pid {
+                    let _ = kill(Pid::from_raw(pid as i32), Signal::SIGKILL);
+                }
+                Err(timed_out(&program))
+            }
+        }
+    }
+}
+
+fn pin_current_thread(cpus: &[usize]) -> nix::Result<()> {
+    let mut set = CpuSet::new();
+    for cpu in cpus {
+        set.set(*cpu)?;
+    }
+    sched_setaffinity(Pid::from_raw(0), &set)
+}
+
+/// nix has no `sched_setscheduler` wrapper (as of 0.29), so this calls libc
+/// and leaves the error handling to nix like `pin_current_thread`
+fn set_fifo_priority(priority: i32) -> nix::Result<()> {
+    let param = libc::sched_param { sched_priority: priority };
+    // pid 0 is the calling thread
+    Errno::result(unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) }).map(drop)
+}
+
+/// Whether the effective capability set allows raising scheduling priority
+pub fn has_cap_sys_nice() -> bool {
+    const CAP_SYS_NICE: u32 = 23;
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let caps = status.lines().find_map(|line| line.strip_prefix("CapEff:"))?;
+            u64::from_str_radix(caps.trim(), 16).ok()
+        })
+        .is_some_and(|caps| caps & (1 << CAP_SYS_NICE) != 0)
+}
+
+fn timed_out(program: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, format!("{} did not finish within {}s", program, TIMEOUT.as_secs()))
+}
+
+/// Runs `command` to completion on the subprocess thread if there is one,
+/// otherwise through `tokio::process`; killed after `TIMEOUT`
+pub async fn output(command: Command, thread: Option<&SubprocessThread>) -> io::Result<Output> {
+    match thread {
+        Some(thread) => thread.output(command).await,
+        None => {
+            let program = command.get_program().to_string_lossy().into_owned();
+            let mut command = tokio::process::Command::from(command);
+            command.kill_on_drop(true);
+            time::timeout(TIMEOUT, command.output()).await.map_err(|_| timed_out(&program))?
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::glob;
+use hyper::header::AUTHORIZATION;
+use hyper::HeaderMap;
+use prometheus::proto::MetricFamily;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Contents of `--tenant-config`
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TenantsFile {
+    tenants: Vec<Tenant>,
+}
+
+/// A team scraping with its own bearer token, shown only the label sets of
+/// its nodes
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Tenant {
+    pub name: String,
+    token: String,
+    /// `nodename` label values, `*` matches any run of characters
+    nodenames: Vec<String>,
+}
+
+impl Tenant {
+    pub fn allows(&self, nodename: &str) -> bool {
+        self.nodenames.iter().any(|pattern| glob::matches(pattern, nodename))
+    }
+
+    /// Drops the label sets without an allowed `nodename`, and the families
+    /// left empty
+    pub fn filter(&self, families: &mut Vec<MetricFamily>) {
+        for family in families.iter_mut() {
+            family.mut_metric().retain(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_name() == "nodename" && self.allows(label.get_value()))
+            });
+        }
+        families.retain(|family| !family.get_metric().is_empty());
+    }
+}
+
+/// Outcome of checking a request's bearer token
+pub enum Identity {
+    /// No bearer token
+    Anonymous,
+    Tenant(Arc<Tenant>),
+    /// A bearer token of no tenant
+    Unknown,
+}
+
+pub struct Tenants {
+    tenants: Vec<Arc<Tenant>>,
+}
+
+impl Tenants {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let file: TenantsFile = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+        for tenant in &file.tenants {
+            if tenant.token.is_empty() {
+                return Err(format!("tenant {} has an empty token", tenant.name).into());
+            }
+        }
+        Ok(Self {
+            tenants: file.tenants.into_iter().map(Arc::new).collect(),
+        })
+    }
+
+    pub fn identify(&self, headers: &HeaderMap) -> Identity {
+        let Some(token) = bearer_token(headers) else {
+            return Identity::Anonymous;
+        };
+        // Compare with every tenant so that the time taken tells nothing
+        // about the tokens
+        let mut found = None;
+        for tenant in &self.tenants {
+            if constant_time_eq(tenant.token.as_bytes(), token.as_bytes()) {
+                found = Some(Arc::clone(tenant));
+            }
+        }
+        found.map_or(Identity::Unknown, Identity::Tenant)
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    Some(value.strip_prefix("Bearer ")?.trim())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Gauge, GaugeVec, Opts, Registry};
+
+    #[test]
+    fn keeps_allowed_nodenames_only() {
+        let registry = Registry::new();
+        let l3 = GaugeVec::new(Opts::new("amd_l3_miss_percent", "L3"), &["nodename"]).unwrap();
+        let build = Gauge::new("amd_uprof_exporter_build_info", "Build").unwrap();
+        registry.register(Box::new(l3.clone())).unwrap();
+        registry.register(Box::new(build.clone())).unwrap();
+        l3.with_label_values(&["gpu-01"]).set(1.0);
+        l3.with_label_values(&["cpu-01"]).set(2.0);
+        build.set(1.0);
+
+        let tenant = Tenant {
+            name: "gpu-team".to_string(),
+            token: "secret".to_string(),
+            nodenames: vec!["gpu-*".to_string()],
+        };
+        let mut families = registry.gather();
+        tenant.filter(&mut families);
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[0].get_metric().len(), 1);
+        assert_eq!(families[0].get_metric()[0].get_gauge().get_value(), 1.0);
+    }
+}
@@ -0,0 +1,129 @@
+//! Stages applied to every collected row before it reaches the gauges.
+
+use crate::columns;
+use serde::Deserialize;
+
+pub trait Transform: Send + Sync {
+    fn apply(&self, values: &mut Vec<f64>);
+}
+
+/// Multiplies one column, e.g. by 1000 to turn GB/s into MB/s
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Scale {
+    pub index: usize,
+    pub factor: f64,
+}
+
+impl Transform for Scale {
+    fn apply(&self, values: &mut Vec<f64>) {
+        values[self.index] *= self.factor;
+    }
+}
+
+/// Limits every column to `[min, max]`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Clamp {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Transform for Clamp {
+    fn apply(&self, values: &mut Vec<f64>) {
+        for value in values.iter_mut() {
+            *value = value.clamp(self.min, self.max);
+        }
+    }
+}
+
+/// Swaps one exact value of a column for another, e.g. a `-1` sentinel for `nan`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Replace {
+    pub index: usize,
+    pub from: f64,
+    pub to: f64,
+}
+
+impl Transform for Replace {
+    fn apply(&self, values: &mut Vec<f64>) {
+        let value = &mut values[self.index];
+        if *value == self.from || (value.is_nan() && self.from.is_nan()) {
+            *value = self.to;
+        }
+    }
+}
+
+/// `[[transforms]]` entry of the config file, selected by its `type` key
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransformConfig {
+    Scale(Scale),
+    Clamp(Clamp),
+    Replace(Replace),
+}
+
+impl TransformConfig {
+    /// Column the stage touches, `None` for stages that cover the whole row
+    fn index(&self) -> Option<usize> {
+        match self {
+            TransformConfig::Scale(scale) => Some(scale.index),
+            TransformConfig::Clamp(_) => None,
+            TransformConfig::Replace(replace) => Some(replace.index),
+        }
+    }
+
+    fn into_transform(self) -> Box<dyn Transform> {
+        match self {
+            TransformConfig::Scale(scale) => Box::new(scale),
+            TransformConfig::Clamp(clamp) => Box::new(clamp),
+            TransformConfig::Replace(replace) => Box::new(replace),
+        }
+    }
+}
+
+/// Rejects stages that would panic on the first row, e.g. a clamp whose
+/// `min` exceeds its `max`
+pub fn validate(configs: &[TransformConfig]) -> Result<(), String> {
+    for config in configs {
+        if let TransformConfig::Clamp(clamp) = config {
+            if clamp.min.is_nan() || clamp.max.is_nan() || clamp.min > clamp.max {
+                return Err(format!("invalid clamp transform, min {} and max {}", clamp.min, clamp.max));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Configured stages in order; stages pointing past the last column are
+/// dropped with a warning
+pub fn pipeline(configs: &[TransformConfig]) -> Vec<Box<dyn Transform>> {
+    configs
+        .iter()
+        .filter(|config| {
+            let valid = config.index().is_none_or(|index| index < columns::COUNT);
+            if !valid {
+                eprintln!("Warning: ignoring transform {:?}, there are {} columns", config, columns::COUNT);
+            }
+            valid
+        })
+        .cloned()
+        .map(TransformConfig::into_transform)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_clamps() {
+        let clamp = |min, max| vec![TransformConfig::Clamp(Clamp { min, max })];
+        assert!(validate(&clamp(0.0, 100.0)).is_ok());
+        assert!(validate(&clamp(5.0, 5.0)).is_ok());
+        assert!(validate(&clamp(100.0, 0.0)).is_err());
+        assert!(validate(&clamp(f64::NAN, 100.0)).is_err());
+        assert!(validate(&clamp(0.0, f64::NAN)).is_err());
+    }
+}
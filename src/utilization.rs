@@ -0,0 +1,52 @@
+use crate::columns;
+use crate::registration::Registrar;
+use prometheus::{GaugeVec, Opts};
+
+/// Utilization above which a warning is printed
+const WARN_PERCENT: f64 = 90.0;
+
+/// Total memory bandwidth as a share of what the installed DIMMs can move
+pub struct MemBwUtilization {
+    nodename: String,
+    max_gbps: f64,
+    utilization: GaugeVec,
+    /// Whether the last collection was above `WARN_PERCENT`, so that the
+    /// warning is printed once per episode
+    saturated: bool,
+}
+
+impl MemBwUtilization {
+    pub fn new(registry: &Registrar, nodename: &str, max_gbps: f64) -> Self {
+        let utilization = GaugeVec::new(
+            Opts::new(
+                "amd_mem_bw_utilization_percent",
+                format!("Total memory bandwidth in percent of {} GB/s", max_gbps),
+            ),
+            &["nodename"],
+        ).unwrap();
+        registry.register(&utilization);
+        Self {
+            nodename: nodename.to_string(),
+            max_gbps,
+            utilization,
+            saturated: false,
+        }
+    }
+
+    pub fn update(&mut self, values: &[f64]) {
+        let bandwidth = values[columns::TOTAL_MEM_BW_GBPS];
+        if bandwidth.is_nan() {
+            return;
+        }
+        let percent = bandwidth / self.max_gbps * 100.0;
+        self.utilization.with_label_values(&[&self.nodename]).set(percent);
+        let saturated = percent > WARN_PERCENT;
+        if saturated && !self.saturated {
+            eprintln!(
+                "Warning: memory bandwidth at {:.1}% of {} GB/s",
+                percent, self.max_gbps
+            );
+        }
+        self.saturated = saturated;
+    }
+}
@@ -0,0 +1,42 @@
+//! `AMDuProfPcm --version` output cached across restarts, keyed by the
+//! binary path and modification time
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+fn cache_file() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".cache/uprof-exporter/uprof-version"))
+}
+
+/// Modification time of the binary in nanoseconds since the epoch
+fn mtime(binary: &Path) -> Option<u128> {
+    let modified = fs::metadata(binary).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos())
+}
+
+/// Cached version of `binary`, unless it changed since it was cached
+pub fn load(binary: &Path) -> Option<String> {
+    let content = fs::read_to_string(cache_file()?).ok()?;
+    let mut lines = content.splitn(3, '\n');
+    let (path, cached_mtime, version) = (lines.next()?, lines.next()?, lines.next()?);
+    if Path::new(path) != binary || cached_mtime.parse::<u128>().ok()? != mtime(binary)? {
+        return None;
+    }
+    Some(version.to_string())
+}
+
+pub fn store(binary: &Path, version: &str) {
+    let (Some(file), Some(mtime)) = (cache_file(), mtime(binary)) else {
+        return;
+    };
+    let content = format!("{}\n{}\n{}", binary.display(), mtime, version);
+    let stored = file
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&file, content));
+    if let Err(e) = stored {
+        eprintln!("Warning: cannot cache the AMDuProfPcm version in {}: {}", file.display(), e);
+    }
+}
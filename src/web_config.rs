@@ -0,0 +1,134 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::header::AUTHORIZATION;
+use hyper::HeaderMap;
+use serde::Deserialize;
+use ring::digest::{digest, SHA256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+/// Contents of the Prometheus `--web.config.file`, as understood by
+/// node_exporter. Settings other than the certificate pair and the users
+/// are refused rather than ignored, since most of them tighten security.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct WebConfig {
+    pub tls_server_config: Option<TlsServerConfig>,
+    /// bcrypt hashes of the passwords keyed by user name
+    pub basic_auth_users: HashMap<String, String>,
+    #[serde(flatten)]
+    unsupported: HashMap<String, serde_yaml::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TlsServerConfig {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    #[serde(flatten)]
+    unsupported: HashMap<String, serde_yaml::Value>,
+}
+
+impl WebConfig {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)?;
+        let config: Self = serde_yaml::from_str(&content)?;
+        let tls_unsupported = config.tls_server_config.iter().flat_map(|tls| tls.unsupported.keys());
+        let mut unsupported: Vec<&String> = config.unsupported.keys().chain(tls_unsupported).collect();
+        if !unsupported.is_empty() {
+            unsupported.sort();
+            let keys: Vec<&str> = unsupported.iter().map(|key| key.as_str()).collect();
+            return Err(format!("unsupported settings {}", keys.join(", ")).into());
+        }
+        Ok(config)
+    }
+
+    pub fn tls_acceptor(&self) -> Result<Option<TlsAcceptor>, Box<dyn std::error::Error>> {
+        let Some(tls) = &self.tls_server_config else {
+            return Ok(None);
+        };
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&tls.cert_file)?))?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        let key = private_key(&tls.key_file)?;
+        let mut config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(Some(TlsAcceptor::from(Arc::new(config))))
+    }
+
+    pub fn basic_auth(&self) -> Option<BasicAuth> {
+        (!self.basic_auth_users.is_empty()).then(|| BasicAuth {
+            users: Arc::new(self.basic_auth_users.clone()),
+            verified: Arc::new(Mutex::new(HashSet::new())),
+        })
+    }
+}
+
+/// First PKCS#8, RSA or SEC1 key in a PEM file
+fn private_key(path: &Path) -> Result<PrivateKey, Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(format!("no private key in {}", path.display()).into())
+}
+
+/// Successful verifications remembered, cleared once this many are
+const MAX_VERIFIED: usize = 1024;
+
+/// HTTP basic authentication against the bcrypt hashes of `basic_auth_users`
+pub struct BasicAuth {
+    users: Arc<HashMap<String, String>>,
+    /// SHA-256 of credentials bcrypt accepted, so that like in node_exporter
+    /// only the first scrape with them pays for bcrypt
+    verified: Arc<Mutex<HashSet<Vec<u8>>>>,
+}
+
+impl BasicAuth {
+    pub async fn check(&self, headers: &HeaderMap) -> bool {
+        let Some((user, password)) = credentials(headers) else {
+            return false;
+        };
+        let Some(hash) = self.users.get(&user).cloned() else {
+            return false;
+        };
+        // The bcrypt hash is part of the key, a changed hash needs a new check
+        let key = digest(&SHA256, format!("{}\0{}\0{}", user, password, hash).as_bytes()).as_ref().to_vec();
+        if self.verified.lock().unwrap().contains(&key) {
+            return true;
+        }
+        // bcrypt is deliberately slow, keep it off the runtime threads
+        let valid = tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash).unwrap_or(false))
+            .await
+            .unwrap_or(false);
+        if valid {
+            let mut verified = self.verified.lock().unwrap();
+            if verified.len() >= MAX_VERIFIED {
+                verified.clear();
+            }
+            verified.insert(key);
+        }
+        valid
+    }
+}
+
+fn credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}